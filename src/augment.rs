@@ -0,0 +1,325 @@
+//! Summaries which every node keeps about its subtree. They are chosen by the storage,
+//! so all trees in a [`SharedVecStorage`](crate::SharedVecStorage) maintain the same summaries.
+//!
+//! Summaries are recomputed along the modified path and for the nodes of each rotation, once the tree is balanced.
+//! A panicking augmentation therefore only leaves summaries outdated, but the tree intact.
+//! This allows queries like [`RedBlackTreeSet::range_fold`](crate::RedBlackTreeSet::range_fold) in O(log n).
+
+/// Aggregates items into a summary per subtree, forming a monoid:
+/// `combine` has to be associative with `empty` as identity. It's called with the summaries in item order.
+///
+/// Like comparators, augmentations of a [`SharedVecStorage`](crate::SharedVecStorage) may read its trees, but modifying them panics.
+///
+/// ```
+/// use vec_multi_tree::{Augment, RedBlackTreeSet};
+///
+/// struct Sum;
+///
+/// impl Augment<u32> for Sum {
+///     type Summary = u64;
+///
+///     fn empty(&self) -> u64 {
+///         0
+///     }
+///     fn single(&self, item: &u32) -> u64 {
+///         *item as u64
+///     }
+///     fn combine(&self, left: &u64, right: &u64) -> u64 {
+///         left + right
+///     }
+/// }
+///
+/// let mut tree = RedBlackTreeSet::with_augment(Sum);
+/// for x in 1..=10 {
+///     tree.insert(x);
+/// }
+/// assert_eq!(3 + 4 + 5, tree.range_fold(3..6));
+/// ```
+pub trait Augment<T> {
+    type Summary;
+
+    fn empty(&self) -> Self::Summary;
+    fn single(&self, item: &T) -> Self::Summary;
+    fn combine(&self, left: &Self::Summary, right: &Self::Summary) -> Self::Summary;
+
+    /// Number of items `summary` covers, for augmentations which count them. Iterators use it to skip in O(log n).
+    fn len(&self, summary: &Self::Summary) -> Option<usize> {
+        let _ = summary;
+        None
+    }
+}
+
+/// Augmentations counting the items of each subtree, which enables
+/// [`RedBlackTreeSet::select`](crate::RedBlackTreeSet::select) and [`RedBlackTreeSet::rank`](crate::RedBlackTreeSet::rank).
+/// Implementors guarantee that [`Augment::len`] never returns `None`.
+pub trait OrderStatistic<T>: Augment<T> {}
+
+/// Default for all storages. The summary is zero-sized, so nodes don't grow and no summaries are updated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NoAugment;
+
+impl<T> Augment<T> for NoAugment {
+    type Summary = ();
+
+    #[inline(always)]
+    fn empty(&self) {}
+
+    #[inline(always)]
+    fn single(&self, _item: &T) {}
+
+    #[inline(always)]
+    fn combine(&self, _left: &(), _right: &()) {}
+}
+
+/// Stores the number of items per subtree, which costs a `usize` per node
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SubtreeSize;
+
+impl<T> Augment<T> for SubtreeSize {
+    type Summary = usize;
+
+    #[inline(always)]
+    fn empty(&self) -> usize {
+        0
+    }
+
+    #[inline(always)]
+    fn single(&self, _item: &T) -> usize {
+        1
+    }
+
+    #[inline(always)]
+    fn combine(&self, left: &usize, right: &usize) -> usize {
+        left + right
+    }
+
+    #[inline(always)]
+    fn len(&self, summary: &usize) -> Option<usize> {
+        Some(*summary)
+    }
+}
+
+impl<T> OrderStatistic<T> for SubtreeSize {}
+
+/// Maintains both summaries side by side, e.g. `(SubtreeSize, Sum)` for sums with order statistics
+impl<T, A: Augment<T>, B: Augment<T>> Augment<T> for (A, B) {
+    type Summary = (A::Summary, B::Summary);
+
+    fn empty(&self) -> Self::Summary {
+        (self.0.empty(), self.1.empty())
+    }
+
+    fn single(&self, item: &T) -> Self::Summary {
+        (self.0.single(item), self.1.single(item))
+    }
+
+    fn combine(&self, left: &Self::Summary, right: &Self::Summary) -> Self::Summary {
+        (
+            self.0.combine(&left.0, &right.0),
+            self.1.combine(&left.1, &right.1),
+        )
+    }
+
+    fn len(&self, summary: &Self::Summary) -> Option<usize> {
+        self.0.len(&summary.0).or_else(|| self.1.len(&summary.1))
+    }
+}
+
+impl<T, A: OrderStatistic<T>, B: Augment<T>> OrderStatistic<T> for (A, B) {}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        cell::{Cell, RefCell},
+        ops::Bound,
+    };
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+    use crate::{storage::InternalStorage, RedBlackTreeSet, SharedVecStorage, SortedByKey};
+
+    struct Sum;
+
+    impl Augment<(u8, i64)> for Sum {
+        type Summary = i64;
+
+        fn empty(&self) -> i64 {
+            0
+        }
+        fn single(&self, item: &(u8, i64)) -> i64 {
+            item.1
+        }
+        fn combine(&self, left: &i64, right: &i64) -> i64 {
+            left + right
+        }
+    }
+
+    /// Not commutative, so it catches summaries combined out of order
+    struct Concat;
+
+    impl Augment<u8> for Concat {
+        type Summary = String;
+
+        fn empty(&self) -> String {
+            String::new()
+        }
+        fn single(&self, item: &u8) -> String {
+            format!("{item},")
+        }
+        fn combine(&self, left: &String, right: &String) -> String {
+            format!("{left}{right}")
+        }
+    }
+
+    #[test]
+    fn range_fold_matches_iteration() {
+        let mut tree = RedBlackTreeSet::with_augment(Concat);
+        let values = (0..64u8)
+            .map(|x| x.wrapping_mul(37) % 64)
+            .collect::<Vec<_>>();
+        for x in &values {
+            tree.insert(*x);
+        }
+        for x in values.iter().filter(|x| *x % 5 == 0) {
+            tree.remove(x);
+        }
+        tree.validate_constraints();
+
+        for start in (0..66).step_by(3) {
+            for end in (start..66).step_by(4) {
+                let expected = tree
+                    .range(start..end)
+                    .map(|x| format!("{x},"))
+                    .collect::<String>();
+                assert_eq!(expected, tree.range_fold(start..end));
+                let expected = tree
+                    .range((Bound::Excluded(start), Bound::Included(end)))
+                    .map(|x| format!("{x},"))
+                    .collect::<String>();
+                assert_eq!(
+                    expected,
+                    tree.range_fold((Bound::Excluded(start), Bound::Included(end)))
+                );
+            }
+        }
+        assert_eq!(
+            tree.iter().map(|x| format!("{x},")).collect::<String>(),
+            tree.range_fold::<u8>(..)
+        );
+    }
+
+    #[test]
+    fn sums_with_order_statistics() {
+        let storage = SharedVecStorage::with_augment((SubtreeSize, Sum));
+        let by_key = SortedByKey::new(|x: &(u8, i64)| &x.0);
+        let mut tree = storage.add_empty_tree_with_comparator(by_key);
+        let mut other = storage.add_empty_tree_with_comparator(by_key);
+        for x in 0..20 {
+            tree.insert((x, x as i64 * 10));
+        }
+        assert_eq!(
+            (5, 100 + 110 + 120 + 130 + 140),
+            tree.range_fold(&(10, 0)..&(15, 0))
+        );
+
+        // Replaced items are equal by order, but not by summary
+        tree.replace((12, 0));
+        assert_eq!(
+            (5, 100 + 110 + 130 + 140),
+            tree.range_fold(&(10, 0)..&(15, 0))
+        );
+
+        tree.move_to(tree.find_by_key(&11).unwrap(), &mut other)
+            .unwrap();
+        assert_eq!((4, 100 + 130 + 140), tree.range_fold(&(10, 0)..&(15, 0)));
+        assert_eq!((1, 110), other.range_fold::<(u8, i64)>(..));
+        assert_eq!(
+            Some((12, 0)),
+            tree.select(11).and_then(|x| tree.get_copied(x))
+        );
+        tree.validate_constraints();
+    }
+
+    /// Maximum, which panics in `combine` while armed
+    struct Fragile {
+        armed: Cell<bool>,
+    }
+
+    impl Augment<u32> for Fragile {
+        type Summary = u32;
+
+        fn empty(&self) -> u32 {
+            0
+        }
+        fn single(&self, item: &u32) -> u32 {
+            *item
+        }
+        fn combine(&self, left: &u32, right: &u32) -> u32 {
+            assert!(!self.armed.get(), "Combined while armed");
+            *left.max(right)
+        }
+    }
+
+    #[test]
+    fn panicking_augmentation_keeps_tree_balanced() {
+        let mut tree = RedBlackTreeSet::with_augment(Fragile {
+            armed: Cell::new(false),
+        });
+        for x in 0..64 {
+            tree.insert(x);
+        }
+        tree.nodes.augment().armed.set(true);
+        for x in 64..80 {
+            assert!(catch_unwind(AssertUnwindSafe(|| tree.insert(x))).is_err());
+        }
+        for x in (0..48).step_by(3) {
+            assert!(catch_unwind(AssertUnwindSafe(|| tree.remove(&x))).is_err());
+        }
+        tree.nodes.augment().armed.set(false);
+
+        // Only summaries are outdated, the tree itself was rebalanced before the augmentation ran
+        tree.validate_constraints();
+        let expected = (0..80)
+            .filter(|x| x % 3 != 0 || *x >= 48)
+            .collect::<Vec<_>>();
+        assert_eq!(expected, tree.iter_copied().collect::<Vec<_>>());
+        for x in expected {
+            assert_eq!(Some(x), tree.remove(&x));
+            tree.validate_constraints();
+        }
+        assert!(tree.is_empty());
+    }
+
+    thread_local! {
+        static SIBLING: RefCell<Option<RedBlackTreeSet<&'static SharedVecStorage<u32, Reentrant>>>> =
+            const { RefCell::new(None) };
+    }
+
+    /// Inserts into the tree in [`SIBLING`] whenever it combines
+    struct Reentrant;
+
+    impl Augment<u32> for Reentrant {
+        type Summary = u32;
+
+        fn empty(&self) -> u32 {
+            0
+        }
+        fn single(&self, item: &u32) -> u32 {
+            *item
+        }
+        fn combine(&self, left: &u32, right: &u32) -> u32 {
+            SIBLING.with_borrow_mut(|sibling| sibling.as_mut().map(|x| x.insert(0)));
+            left + right
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be modified from comparators or augmentations")]
+    fn augmentation_inserting_into_sibling() {
+        let storage = Box::leak(Box::new(SharedVecStorage::with_augment(Reentrant)));
+        SIBLING.set(Some(storage.add_empty_tree()));
+        let mut tree = storage.add_empty_tree();
+        tree.insert(1);
+        tree.insert(2);
+    }
+}
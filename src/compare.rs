@@ -0,0 +1,261 @@
+use core::{borrow::Borrow, cmp::Ordering};
+
+/// Ordering of a tree. `R` is the type of lookup values, which defaults to the item type.
+///
+/// Implemented by closures `Fn(&T, &T) -> Ordering` and [`OrdCompare`]. Custom types allow comparators
+/// which are configured at runtime or accept multiple lookup types.
+///
+/// Comparators of trees in a [`SharedVecStorage`](crate::SharedVecStorage) may read other trees of the storage,
+/// but modifying them panics, as that could move the compared items.
+pub trait Compare<L: ?Sized, R: ?Sized = L> {
+    fn compare(&self, left: &L, right: &R) -> Ordering;
+}
+
+/// Zero-sized default comparator using [`Ord`]. Like std collections, lookups accept anything the items
+/// can be borrowed as.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OrdCompare;
+
+impl<L: ?Sized + Borrow<R>, R: ?Sized + Ord> Compare<L, R> for OrdCompare {
+    #[inline(always)]
+    fn compare(&self, left: &L, right: &R) -> Ordering {
+        left.borrow().cmp(right)
+    }
+}
+
+impl<T: ?Sized, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    #[inline(always)]
+    fn compare(&self, left: &T, right: &T) -> Ordering {
+        self(left, right)
+    }
+}
+
+/// Orders items by the key `F` extracts from them, so records don't need a separate map for their key.
+/// Trees with this comparator can be searched by key, e.g. with [`RedBlackTreeSet::find_by_key`](crate::RedBlackTreeSet::find_by_key).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SortedByKey<F>(pub F);
+
+impl<F> SortedByKey<F> {
+    /// Bounds `extract_key`, so closures returning a field don't need annotated lifetimes
+    pub fn new<T: ?Sized, K: ?Sized>(extract_key: F) -> Self
+    where
+        F: Fn(&T) -> &K,
+    {
+        SortedByKey(extract_key)
+    }
+
+    pub(crate) fn key<'a, T: ?Sized, K: ?Sized>(&self, item: &'a T) -> &'a K
+    where
+        F: Fn(&T) -> &K,
+    {
+        (self.0)(item)
+    }
+}
+
+impl<T: ?Sized, K: ?Sized + Ord, F: Fn(&T) -> &K> Compare<T> for SortedByKey<F> {
+    #[inline(always)]
+    fn compare(&self, left: &T, right: &T) -> Ordering {
+        self.key(left).cmp(self.key(right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, ops::Bound};
+    use std::panic::AssertUnwindSafe;
+
+    use super::*;
+    use crate::{RedBlackTreeMap, RedBlackTreeSet, SharedVecStorage};
+
+    /// Configured at runtime, compares `String` items with each other and with `str` lookups
+    struct Collation {
+        ignore_case: bool,
+    }
+
+    impl Collation {
+        fn collate(&self, left: &str, right: &str) -> Ordering {
+            if self.ignore_case {
+                let left = left.chars().flat_map(char::to_lowercase);
+                left.cmp(right.chars().flat_map(char::to_lowercase))
+            } else {
+                left.cmp(right)
+            }
+        }
+    }
+
+    impl Compare<String, str> for Collation {
+        fn compare(&self, left: &String, right: &str) -> Ordering {
+            self.collate(left, right)
+        }
+    }
+
+    impl Compare<String> for Collation {
+        fn compare(&self, left: &String, right: &String) -> Ordering {
+            self.collate(left, right)
+        }
+    }
+
+    impl Compare<str> for Collation {
+        fn compare(&self, left: &str, right: &str) -> Ordering {
+            self.collate(left, right)
+        }
+    }
+
+    #[test]
+    fn descending_closure() {
+        let mut tree = RedBlackTreeSet::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        for x in [3, 1, 4, 5, 9, 2, 6] {
+            tree.insert(x);
+        }
+        tree.validate_constraints();
+        assert!(tree.contains(&4));
+        assert_eq!(
+            vec![9, 6, 5, 4, 3, 2, 1],
+            tree.iter_copied().collect::<Vec<_>>()
+        );
+        // Bounds follow the comparator
+        assert_eq!(
+            vec![6, 5, 4],
+            tree.range_copied(&6..=&4).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(9), tree.remove(&9));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        timestamp: u64,
+        name: String,
+    }
+
+    fn record(timestamp: u64, name: &str) -> Record {
+        Record {
+            timestamp,
+            name: String::from(name),
+        }
+    }
+
+    #[test]
+    fn sorted_by_key() {
+        let mut tree =
+            RedBlackTreeSet::with_comparator(SortedByKey::new(|r: &Record| &r.timestamp));
+        tree.insert(record(20, "b"));
+        tree.insert(record(10, "a"));
+        tree.insert(record(30, "c"));
+        assert!(!tree.insert(record(20, "duplicate")).is_new());
+        tree.validate_constraints();
+
+        let handle = tree.find_by_key(&20).unwrap();
+        assert_eq!("b", tree.get(handle).unwrap().name);
+        assert_eq!(None, tree.find_by_key(&25));
+        assert_eq!(
+            vec!["a", "b"],
+            tree.range_by_key(..30)
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, tree.range_by_key(21..30).count());
+        assert_eq!(Some(record(10, "a")), tree.remove_by_key(&10));
+        assert_eq!(None, tree.remove_by_key(&10));
+        assert_eq!(2, tree.len());
+    }
+
+    #[test]
+    fn sorted_by_borrowed_key() {
+        let storage = SharedVecStorage::new();
+        let mut tree =
+            storage.add_empty_tree_with_comparator(SortedByKey::new(|r: &Record| &r.name));
+        tree.insert(record(1, "b"));
+        tree.insert(record(2, "a"));
+        assert!(tree.find_by_key("a").is_some());
+        assert_eq!(Some(record(1, "b")), tree.remove_by_key("b"));
+    }
+
+    #[test]
+    fn runtime_collation() {
+        let mut tree = RedBlackTreeSet::with_comparator(Collation { ignore_case: true });
+        assert!(tree.insert(String::from("b")).is_new());
+        assert!(tree.insert(String::from("A")).is_new());
+        assert!(!tree.insert(String::from("B")).is_new());
+        assert!(tree.contains("a"));
+        assert_eq!(
+            vec!["A", "b"],
+            tree.range::<str>((Bound::Included("a"), Bound::Included("B")))
+                .collect::<Vec<_>>()
+        );
+
+        let mut tree = RedBlackTreeSet::with_comparator(Collation { ignore_case: false });
+        tree.insert(String::from("b"));
+        assert!(tree.insert(String::from("B")).is_new());
+        assert!(!tree.contains("a"));
+        assert_eq!(vec!["B", "b"], tree.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn map_and_multiset_comparators() {
+        let mut map = RedBlackTreeMap::with_comparator(|a: &u8, b: &u8| b.cmp(a));
+        map.insert(1, "one");
+        map.insert(2, "two");
+        *map.entry(1).or_insert("") = "eins";
+        assert_eq!(vec![&2, &1], map.keys().collect::<Vec<_>>());
+        assert_eq!(Some(&"eins"), map.get(&1));
+
+        let storage = SharedVecStorage::new();
+        let mut multiset = storage.add_empty_multiset_with_comparator(|a: &u8, b: &u8| b.cmp(a));
+        for x in [1, 2, 1] {
+            multiset.insert(x);
+        }
+        assert_eq!(2, multiset.count(&1));
+        assert_eq!(vec![2, 1, 1], multiset.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be modified from comparators")]
+    fn comparator_inserting_into_sibling() {
+        let storage = SharedVecStorage::new();
+        let sibling = RefCell::new(storage.add_empty_tree());
+        let mut tree = storage.add_empty_tree_with_comparator(|a: &u32, b: &u32| {
+            // Growing the storage would move the compared items
+            sibling.borrow_mut().insert(*a);
+            a.cmp(b)
+        });
+        tree.insert(1);
+        tree.insert(2);
+    }
+
+    #[test]
+    fn comparator_reading_sibling() {
+        let storage = SharedVecStorage::new();
+        let sibling = RefCell::new(storage.add_empty_tree());
+        let mut tree = storage.add_empty_tree_with_comparator(|a: &u32, b: &u32| {
+            if sibling.borrow().contains(a) {
+                sibling.borrow_mut().remove(a);
+            }
+            a.cmp(b)
+        });
+        tree.insert(1);
+        sibling.borrow_mut().insert(1);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| tree.insert(2)));
+        assert!(result.is_err());
+
+        // The storage accepts modifications again once the comparator unwound
+        sibling.borrow_mut().remove(&1);
+        sibling.borrow_mut().insert(3);
+        assert!(tree.insert(2).is_new());
+        assert_eq!(vec![1, 2], tree.iter_copied().collect::<Vec<_>>());
+        assert_eq!(vec![3], sibling.borrow().iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be modified from comparators")]
+    fn key_extractor_inserting_into_sibling() {
+        let storage = SharedVecStorage::new();
+        let by_name = SortedByKey::new(|r: &Record| &r.name);
+        let sibling = RefCell::new(storage.add_empty_tree_with_comparator(by_name));
+        let mut tree = storage.add_empty_tree_with_comparator(SortedByKey::new(|r: &Record| {
+            sibling.borrow_mut().insert(record(r.timestamp, "copy"));
+            &r.timestamp
+        }));
+        tree.insert(record(1, "a"));
+        tree.find_by_key(&1);
+    }
+}
@@ -0,0 +1,430 @@
+//! Cursors point to an item or to the ghost position between the last and the first item, like
+//! the cursors of `std::collections::LinkedList`. Stepping follows parent links, so no stack is kept.
+
+use core::ops::Bound;
+
+use crate::key::OptionKey;
+use crate::storage::{InternalRefStorage, InternalStorage, Storage};
+use crate::{Compare, NodeHandle, OrdCompare, RedBlackTreeSet};
+
+#[cfg(feature = "alloc")]
+use crate::{Augment, MoveError, SharedVecStorage};
+
+/// Read-only cursor, created by [`RedBlackTreeSet::cursor_front`] and friends
+pub struct Cursor<'a, TStorage, C = OrdCompare> {
+    tree: &'a RedBlackTreeSet<TStorage, C>,
+    current: OptionKey,
+}
+
+/// Cursor which can remove and insert items at its position, created by [`RedBlackTreeSet::cursor_front_mut`] and friends
+pub struct CursorMut<'a, TStorage, C = OrdCompare> {
+    tree: &'a mut RedBlackTreeSet<TStorage, C>,
+    current: OptionKey,
+}
+
+/// The value passed to [`CursorMut::insert_before`] or [`CursorMut::insert_after`] doesn't sort
+/// strictly between the items around the insert position. Like in std, the value is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnorderedError;
+
+impl core::fmt::Display for UnorderedError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("value doesn't fit between the neighbours of the cursor")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnorderedError {}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
+    pub(crate) fn cursor_with(&self, current: OptionKey) -> Cursor<'_, TStorage, C> {
+        Cursor {
+            tree: self,
+            current,
+        }
+    }
+
+    pub(crate) fn cursor_mut_with(&mut self, current: OptionKey) -> CursorMut<'_, TStorage, C> {
+        CursorMut {
+            tree: self,
+            current,
+        }
+    }
+
+    /// Node after `current`, where the ghost position is followed by the first node
+    fn next_node(&self, current: OptionKey) -> OptionKey {
+        match current.get() {
+            Some(node_idx) => self.nodes.successor(node_idx),
+            None => self.first_node().map_or(OptionKey::none(), OptionKey::new),
+        }
+    }
+
+    /// Node before `current`, where the ghost position is preceded by the last node
+    fn prev_node(&self, current: OptionKey) -> OptionKey {
+        match current.get() {
+            Some(node_idx) => self.nodes.predecessor(node_idx),
+            None => self.last_node().map_or(OptionKey::none(), OptionKey::new),
+        }
+    }
+
+    /// Safety: References musten't be accessible in safe code, if TStorage doesn't implement InternalRefStorage
+    unsafe fn value_of(&self, node: OptionKey) -> Option<&<TStorage as Storage>::Item> {
+        Some(&self.nodes.get(node.get()?).value)
+    }
+}
+
+impl<'a, TStorage: InternalStorage, C> Cursor<'a, TStorage, C> {
+    /// Handle of the current item, `None` at the ghost position
+    pub fn handle(&self) -> Option<NodeHandle> {
+        self.current
+            .get()
+            .map(|node_idx| self.tree.handle(node_idx))
+    }
+
+    /// Moves to the next item. Moves from the last item to the ghost position and from there to the first item.
+    pub fn move_next(&mut self) {
+        self.current = self.tree.next_node(self.current);
+    }
+
+    /// Moves to the previous item, mirroring [`Cursor::move_next`]
+    pub fn move_prev(&mut self) {
+        self.current = self.tree.prev_node(self.current);
+    }
+
+    pub fn current(&self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.current) }
+    }
+
+    pub fn current_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        unsafe { self.tree.value_of(self.current).copied() }
+    }
+
+    pub fn peek_next(&self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.tree.next_node(self.current)) }
+    }
+
+    pub fn peek_next_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        unsafe {
+            self.tree
+                .value_of(self.tree.next_node(self.current))
+                .copied()
+        }
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.tree.prev_node(self.current)) }
+    }
+
+    pub fn peek_prev_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        unsafe {
+            self.tree
+                .value_of(self.tree.prev_node(self.current))
+                .copied()
+        }
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>> Cursor<'_, TStorage, C> {
+    /// Moves to the first item which isn't below `bound`, or to the ghost position if there is none
+    pub fn seek<Q: ?Sized>(&mut self, bound: Bound<&Q>)
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.current = self.tree.lower_bound_node(bound);
+    }
+}
+
+impl<TStorage: InternalStorage, C> CursorMut<'_, TStorage, C> {
+    /// Read-only cursor at the same position
+    pub fn as_cursor(&self) -> Cursor<'_, TStorage, C> {
+        self.tree.cursor_with(self.current)
+    }
+
+    /// Handle of the current item, `None` at the ghost position
+    pub fn handle(&self) -> Option<NodeHandle> {
+        self.as_cursor().handle()
+    }
+
+    /// Moves to the next item, like [`Cursor::move_next`]
+    pub fn move_next(&mut self) {
+        self.current = self.tree.next_node(self.current);
+    }
+
+    /// Moves to the previous item, like [`Cursor::move_prev`]
+    pub fn move_prev(&mut self) {
+        self.current = self.tree.prev_node(self.current);
+    }
+
+    pub fn current(&self) -> Option<&<TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.current) }
+    }
+
+    pub fn current_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.as_cursor().current_copied()
+    }
+
+    pub fn peek_next(&self) -> Option<&<TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.tree.next_node(self.current)) }
+    }
+
+    pub fn peek_next_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.as_cursor().peek_next_copied()
+    }
+
+    pub fn peek_prev(&self) -> Option<&<TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+    {
+        unsafe { self.tree.value_of(self.tree.prev_node(self.current)) }
+    }
+
+    pub fn peek_prev_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.as_cursor().peek_prev_copied()
+    }
+
+    /// Removes the current item and moves to the next one. Does nothing at the ghost position.
+    pub fn remove_current(&mut self) -> Option<<TStorage as Storage>::Item> {
+        let node_idx = self.current.get()?;
+        // Removal relinks nodes instead of moving values, so the index of the successor stays valid
+        self.current = self.tree.nodes.successor(node_idx);
+        Some(self.tree.remove_node(node_idx))
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    CursorMut<'_, TStorage, C>
+{
+    /// Moves to the first item which isn't below `bound`, like [`Cursor::seek`]
+    pub fn seek<Q: ?Sized>(&mut self, bound: Bound<&Q>)
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.current = self.tree.lower_bound_node(bound);
+    }
+
+    /// Inserts `value` between the previous and the current item without searching from the root.
+    /// At the ghost position, `value` becomes the last item. The cursor doesn't move.
+    pub fn insert_before(
+        &mut self,
+        value: <TStorage as Storage>::Item,
+    ) -> Result<NodeHandle, UnorderedError> {
+        let prev = self.tree.prev_node(self.current);
+        self.check_order(prev, &value, self.current)?;
+        // Either the current node has a free left slot, or its predecessor is the rightmost node of the left subtree
+        let (parent, is_right) = match self.current.get() {
+            Some(node_idx) if self.tree.nodes.get(node_idx).left.get().is_none() => {
+                (self.current, false)
+            }
+            _ => (prev, true),
+        };
+        let node_idx = self.tree.insert_leaf(value, parent, is_right);
+        Ok(self.tree.handle(node_idx))
+    }
+
+    /// Inserts `value` between the current and the next item, mirroring [`CursorMut::insert_before`].
+    /// At the ghost position, `value` becomes the first item.
+    pub fn insert_after(
+        &mut self,
+        value: <TStorage as Storage>::Item,
+    ) -> Result<NodeHandle, UnorderedError> {
+        let next = self.tree.next_node(self.current);
+        self.check_order(self.current, &value, next)?;
+        let (parent, is_right) = match self.current.get() {
+            Some(node_idx) if self.tree.nodes.get(node_idx).right.get().is_none() => {
+                (self.current, true)
+            }
+            _ => (next, false),
+        };
+        let node_idx = self.tree.insert_leaf(value, parent, is_right);
+        Ok(self.tree.handle(node_idx))
+    }
+
+    /// Whether `value` sorts strictly between the nodes `prev` and `next`, where missing nodes are unbounded
+    fn check_order(
+        &self,
+        prev: OptionKey,
+        value: &<TStorage as Storage>::Item,
+        next: OptionKey,
+    ) -> Result<(), UnorderedError> {
+        let is_after_prev = prev
+            .get()
+            .is_none_or(|prev| self.tree.compare_node_value(prev, value).is_lt());
+        let is_before_next = next
+            .get()
+            .is_none_or(|next| self.tree.compare_node_value(next, value).is_gt());
+        if is_after_prev && is_before_next {
+            Ok(())
+        } else {
+            Err(UnorderedError)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'s, T, A: Augment<T>, C: Compare<T>> CursorMut<'_, &'s SharedVecStorage<T, A>, C> {
+    /// Moves the current node into `dest` like [`RedBlackTreeSet::move_to`] and moves the cursor to the next item.
+    /// Returns the handle of the moved node, or `None` at the ghost position.
+    ///
+    /// Panics if `dest` doesn't share the storage with the tree of the cursor
+    pub fn move_current_to(
+        &mut self,
+        dest: &mut RedBlackTreeSet<&'s SharedVecStorage<T, A>, C>,
+    ) -> Result<Option<NodeHandle>, MoveError> {
+        let Some(node_idx) = self.current.get() else {
+            return Ok(None);
+        };
+        let next = self.tree.nodes.successor(node_idx);
+        self.tree.move_node(node_idx, dest)?;
+        self.current = next;
+        Ok(Some(dest.handle(node_idx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_and_seek() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in [10, 20, 30] {
+            tree.insert(x);
+        }
+        let mut cursor = tree.cursor_front();
+        assert_eq!(Some(&10), cursor.current());
+        assert_eq!(None, cursor.peek_prev());
+        cursor.move_next();
+        assert_eq!(Some(&20), cursor.current());
+        assert_eq!(Some(&30), cursor.peek_next());
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(None, cursor.current());
+        assert_eq!(Some(&10), cursor.peek_next());
+        assert_eq!(Some(&30), cursor.peek_prev());
+        cursor.move_prev();
+        assert_eq!(Some(&30), cursor.current());
+
+        cursor.seek(Bound::Excluded(&10));
+        assert_eq!(tree.find(&20), cursor.handle());
+        cursor.seek(Bound::Included(&31));
+        assert_eq!(None, cursor.handle());
+
+        let cursor = tree.cursor_at(tree.find(&30).unwrap()).unwrap();
+        assert_eq!(Some(&20), cursor.peek_prev());
+        assert_eq!(Some(&30), tree.cursor_back().current());
+    }
+
+    #[test]
+    fn remove_and_insert_at_cursor() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in (0..20).map(|x| x * 10) {
+            tree.insert(x);
+        }
+
+        let mut cursor = tree.cursor_front_mut();
+        while let Some(x) = cursor.current_copied() {
+            if x % 20 == 0 {
+                assert_eq!(Some(x), cursor.remove_current());
+            } else {
+                assert_eq!(Err(UnorderedError), cursor.insert_before(x));
+                assert_eq!(Err(UnorderedError), cursor.insert_after(x));
+                cursor.insert_before(x - 1).unwrap();
+                cursor.insert_after(x + 1).unwrap();
+                cursor.move_next();
+                cursor.move_next();
+            }
+        }
+        // At the ghost position, inserts go to the ends
+        cursor.insert_before(200).unwrap();
+        cursor.insert_after(-1).unwrap();
+        assert_eq!(Err(UnorderedError), cursor.insert_after(-1));
+
+        tree.validate_constraints();
+        let expected = core::iter::once(-1)
+            .chain((0..10).flat_map(|x| [x * 20 + 9, x * 20 + 10, x * 20 + 11]))
+            .chain([200])
+            .collect::<Vec<_>>();
+        assert_eq!(expected, tree.iter_copied().collect::<Vec<_>>());
+        assert_eq!(expected.len(), tree.len());
+    }
+
+    #[test]
+    fn insert_into_empty_tree() {
+        let mut tree = RedBlackTreeSet::new();
+        let mut cursor = tree.cursor_back_mut();
+        let handle = cursor.insert_after(1).unwrap();
+        assert_eq!(None, cursor.remove_current());
+        cursor.move_next();
+        assert_eq!(Some(handle), cursor.handle());
+        assert_eq!(Some(&1), cursor.current());
+    }
+
+    #[test]
+    fn move_current_to() {
+        let storage = SharedVecStorage::new();
+        let mut tree = storage.add_empty_tree();
+        let mut odd = storage.add_empty_tree();
+        for x in 0..10 {
+            tree.insert(x);
+        }
+        odd.insert(5);
+
+        let mut cursor = tree.cursor_front_mut();
+        while let Some(x) = cursor.current_copied() {
+            if x % 2 == 0 {
+                cursor.move_next();
+            } else if x == 5 {
+                assert_eq!(
+                    Err(MoveError::Occupied(odd.find(&5).unwrap())),
+                    cursor.move_current_to(&mut odd)
+                );
+                cursor.move_next();
+            } else {
+                let handle = cursor.move_current_to(&mut odd).unwrap().unwrap();
+                assert_eq!(Some(x), odd.get_copied(handle));
+            }
+        }
+        assert_eq!(Ok(None), cursor.move_current_to(&mut odd));
+
+        tree.validate_constraints();
+        odd.validate_constraints();
+        assert_eq!(
+            vec![0, 2, 4, 5, 6, 8],
+            tree.iter_copied().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![1, 3, 5, 7, 9], odd.iter_copied().collect::<Vec<_>>());
+    }
+}
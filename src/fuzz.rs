@@ -1,20 +1,34 @@
 use std::collections::BTreeSet;
 
-use super::node::{Color, Node};
-use super::storage::InternalStorage;
+use super::key::OptionKey;
+use super::node::Color;
 use super::storage::VecStorage;
-use super::RedBlackTreeSet;
+use super::storage::{InternalStorage, StorageNode};
+use super::{RedBlackTreeSet, SubtreeSize};
 
-impl<T> RedBlackTreeSet<VecStorage<T>> {
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
     pub fn validate_constraints(&self) {
-        let root_node = &self.nodes.get(self.root);
+        let Some(root) = self.root.get() else {
+            return;
+        };
+        let root_node = &self.nodes.get(root);
         assert_eq!(root_node.color, Color::Black);
+        assert_eq!(root_node.parent.get(), None);
         self.black_count(root_node, Color::Black);
+        if self.nodes.subtree_len(self.root).is_some() {
+            assert_eq!(self.len, self.counted_len(self.root));
+        }
     }
-    pub(super) fn black_count(&self, node: &Node<T>, parent_color: Color) -> u16 {
+    pub(super) fn black_count(&self, node: &StorageNode<TStorage>, parent_color: Color) -> u16 {
         if parent_color == Color::Red && node.color == Color::Red {
             panic!("Two subsequent RED nodes");
         }
+        for child in [node.left, node.right].into_iter().filter_map(|x| x.get()) {
+            assert!(core::ptr::eq(
+                self.nodes.get(self.nodes.get(child).parent.unwrap()),
+                node
+            ));
+        }
         (match (node.left.get(), node.right.get()) {
             (None, None) => 0,
             (None, Some(right)) => self.black_count(self.nodes.get(right), node.color),
@@ -27,14 +41,24 @@ impl<T> RedBlackTreeSet<VecStorage<T>> {
             }
         }) + (node.color == Color::Black) as u16
     }
+
+    /// Counts the nodes of a subtree and compares them with the summaries of the augmentation
+    fn counted_len(&self, node: OptionKey) -> usize {
+        let Some(node_idx) = node.get() else {
+            return 0;
+        };
+        let node_ref = self.nodes.get(node_idx);
+        let len = self.counted_len(node_ref.left) + self.counted_len(node_ref.right) + 1;
+        assert_eq!(Some(len), self.nodes.subtree_len(node));
+        len
+    }
 }
 
+/// Counts subtrees, so fuzzing covers the maintenance of summaries as well
 pub(super) fn build_fuzz_tree<const LOG: bool>(
     data: &[u8],
-) -> Option<RedBlackTreeSet<VecStorage<&u8>>> {
-    let mut iter = data.iter();
-    let first = iter.next()?;
-    let mut tree = RedBlackTreeSet::new(first);
+) -> RedBlackTreeSet<VecStorage<&u8, SubtreeSize>> {
+    let mut tree = RedBlackTreeSet::with_augment(SubtreeSize);
     for x in data {
         #[cfg(test)]
         if LOG {
@@ -42,23 +66,45 @@ pub(super) fn build_fuzz_tree<const LOG: bool>(
         }
         tree.insert(x);
         if LOG {
-            println!("Root: {}\n{}", tree.root, tree.nodes.debug_str())
+            println!("Root: {:?}\n{}", tree.root, tree.nodes.debug_str())
         }
     }
     if LOG {
         println!("Done inserting");
     }
-    Some(tree)
+    tree
 }
 pub fn fuzz_insert(data: &[u8]) {
-    let Some(tree) = build_fuzz_tree::<false>(data) else {
-        return;
-    };
+    let tree = build_fuzz_tree::<false>(data);
     tree.validate_constraints();
     let collected = tree.iter().copied().collect::<Vec<_>>();
     let expected = data.iter().collect::<BTreeSet<_>>();
     assert_eq!(expected.len(), collected.len());
-    for (a, b) in tree.iter().zip(expected.iter()) {
+    assert_eq!(expected.len(), tree.len());
+    for (i, (a, b)) in tree.iter().zip(expected.iter()).enumerate() {
         assert_eq!(a, b);
+        assert_eq!(i, tree.rank(*b));
+        assert_eq!(Some(a), tree.select(i).and_then(|handle| tree.get(handle)));
+        assert_eq!(Some(a), tree.iter().nth(i));
+    }
+}
+
+pub fn fuzz_remove(data: &[u8]) {
+    let mut tree = build_fuzz_tree::<false>(data);
+    let mut expected = data.iter().collect::<BTreeSet<_>>();
+    for x in data.iter().rev() {
+        assert_eq!(expected.remove(&x), tree.remove(&x).is_some());
+        assert_eq!(expected.len(), tree.len());
+        tree.validate_constraints();
+        assert!(tree.iter().eq(expected.iter()));
+    }
+
+    // Reinsert into the vacated slots
+    for x in data {
+        tree.insert(x);
+        expected.insert(x);
     }
+    tree.validate_constraints();
+    assert_eq!(expected.len(), tree.len());
+    assert!(tree.iter().eq(expected.iter()));
 }
@@ -0,0 +1,80 @@
+/// Opaque reference to a node of a tree, returned by `insert` and `find`.
+///
+/// Unlike a plain index, it can't be confused with counts or positions. Operations taking a handle
+/// check whether it belongs to the tree they are called on. The generation of the storage slot
+/// detects handles whose node was removed, even if the slot was reused for another node since.
+/// Handles of other storages are told apart by the id of their storage, as their indices might be occupied in any storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+    index: usize,
+    generation: u32,
+    storage: u32,
+}
+
+impl NodeHandle {
+    #[inline(always)]
+    pub(crate) const fn new(index: usize, generation: u32, storage: u32) -> Self {
+        Self {
+            index,
+            generation,
+            storage,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) const fn index(self) -> usize {
+        self.index
+    }
+
+    #[inline(always)]
+    pub(crate) const fn generation(self) -> u32 {
+        self.generation
+    }
+
+    #[inline(always)]
+    pub(crate) const fn storage(self) -> u32 {
+        self.storage
+    }
+}
+
+/// Outcome of [`RedBlackTreeSet::insert`](crate::RedBlackTreeSet::insert)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inserted {
+    /// A node was created for the value
+    New(NodeHandle),
+    /// An equal item was already present, the value was dropped
+    Existing(NodeHandle),
+}
+
+impl Inserted {
+    pub fn handle(self) -> NodeHandle {
+        match self {
+            Inserted::New(handle) | Inserted::Existing(handle) => handle,
+        }
+    }
+
+    pub fn is_new(self) -> bool {
+        matches!(self, Inserted::New(_))
+    }
+}
+
+/// Reasons why an operation rejected a [`NodeHandle`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The node was removed. Its slot might hold another node by now.
+    Stale,
+    /// The node belongs to another tree, e.g. one sharing the same storage or one the node was split off into
+    ForeignTree,
+}
+
+impl core::fmt::Display for HandleError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HandleError::Stale => f.write_str("node of the handle was removed"),
+            HandleError::ForeignTree => f.write_str("node of the handle belongs to another tree"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HandleError {}
@@ -0,0 +1,437 @@
+//! Half-open intervals `start..end`, ordered by start and searchable for overlaps.
+//!
+//! Every node keeps the largest end of its subtree ([`MaxEnd`]), so subtrees ending before a query are skipped.
+//! Listing `k` matches costs O((k + 1) log n).
+
+use core::{
+    cmp::Ordering,
+    iter::FusedIterator,
+    ops::{Bound, Range},
+};
+
+use crate::key::OptionKey;
+use crate::storage::{InternalRefStorage, InternalStorage, Storage};
+use crate::{Augment, Compare, HandleError, Inserted, Iter, NodeHandle, RedBlackTreeSet};
+
+#[cfg(feature = "alloc")]
+use crate::storage::{SharedVecStorage, VecStorage};
+
+/// Largest end of the intervals in a subtree, `None` for empty subtrees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaxEnd;
+
+impl<K: Ord + Clone> Augment<Range<K>> for MaxEnd {
+    type Summary = Option<K>;
+
+    fn empty(&self) -> Option<K> {
+        None
+    }
+
+    fn single(&self, item: &Range<K>) -> Option<K> {
+        Some(item.end.clone())
+    }
+
+    fn combine(&self, left: &Option<K>, right: &Option<K>) -> Option<K> {
+        left.as_ref().max(right.as_ref()).cloned()
+    }
+}
+
+/// Orders by start, intervals with equal starts by end
+#[derive(Debug, Default, Clone, Copy)]
+struct IntervalOrder;
+
+impl<K: Ord> Compare<Range<K>> for IntervalOrder {
+    #[inline(always)]
+    fn compare(&self, left: &Range<K>, right: &Range<K>) -> Ordering {
+        left.start
+            .cmp(&right.start)
+            .then_with(|| left.end.cmp(&right.end))
+    }
+}
+
+pub struct IntervalTreeSet<TStorage> {
+    tree: RedBlackTreeSet<TStorage, IntervalOrder>,
+}
+
+#[cfg(feature = "alloc")]
+impl<K: Ord + Clone> IntervalTreeSet<VecStorage<Range<K>, MaxEnd>> {
+    pub fn new() -> Self {
+        IntervalTreeSet {
+            tree: RedBlackTreeSet::with_comparator_and_augment(IntervalOrder, MaxEnd),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K: Ord + Clone> Default for IntervalTreeSet<VecStorage<Range<K>, MaxEnd>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K: Ord + Clone> SharedVecStorage<Range<K>, MaxEnd> {
+    /// Adds an empty interval tree. Its queries clone the intervals, as trees of shared storages don't return references.
+    pub fn add_empty_interval_tree(&self) -> IntervalTreeSet<&SharedVecStorage<Range<K>, MaxEnd>> {
+        IntervalTreeSet {
+            tree: self.add_empty_tree_with_comparator(IntervalOrder),
+        }
+    }
+}
+
+impl<K: Ord + Clone, TStorage: InternalStorage<Item = Range<K>, Augment = MaxEnd>>
+    IntervalTreeSet<TStorage>
+{
+    /// Inserts `interval`, unless an interval with the same start and end exists
+    pub fn insert(&mut self, interval: Range<K>) -> Inserted {
+        self.tree.insert(interval)
+    }
+
+    pub fn remove(&mut self, interval: &Range<K>) -> Option<Range<K>> {
+        self.tree.remove(interval)
+    }
+
+    pub fn find(&self, interval: &Range<K>) -> Option<NodeHandle> {
+        self.tree.find(interval)
+    }
+
+    pub fn contains(&self, interval: &Range<K>) -> bool {
+        self.tree.contains(interval)
+    }
+
+    /// Intervals sharing at least one point with `range`, ordered by start.
+    /// Empty intervals and ranges contain no points, but count as overlapping if they lie strictly inside the other side.
+    pub fn overlapping(&self, range: Range<K>) -> Overlapping<'_, TStorage, K>
+    where
+        TStorage: InternalRefStorage,
+    {
+        self.create_overlapping(Some(range.start), Bound::Excluded(range.end))
+    }
+
+    pub fn overlapping_cloned(&self, range: Range<K>) -> OverlappingCloned<'_, TStorage, K> {
+        OverlappingCloned(self.create_overlapping(Some(range.start), Bound::Excluded(range.end)))
+    }
+
+    /// Intervals containing `point`, ordered by start
+    pub fn stabbing(&self, point: K) -> Overlapping<'_, TStorage, K>
+    where
+        TStorage: InternalRefStorage,
+    {
+        self.create_overlapping(Some(point.clone()), Bound::Included(point))
+    }
+
+    pub fn stabbing_cloned(&self, point: K) -> OverlappingCloned<'_, TStorage, K> {
+        OverlappingCloned(self.create_overlapping(Some(point.clone()), Bound::Included(point)))
+    }
+
+    /// All intervals ordered by start
+    pub fn iter_cloned(&self) -> OverlappingCloned<'_, TStorage, K> {
+        OverlappingCloned(self.create_overlapping(None, Bound::Unbounded))
+    }
+
+    pub fn get_cloned(&self, handle: NodeHandle) -> Option<Range<K>> {
+        let node_idx = self.tree.check_handle(handle).ok()?;
+        let nodes = &self.tree.nodes;
+        Some(nodes.callback(|| nodes.get(node_idx).value.clone()))
+    }
+
+    /// Without `after`, all intervals starting within `before` are listed
+    fn create_overlapping(
+        &self,
+        after: Option<K>,
+        before: Bound<K>,
+    ) -> Overlapping<'_, TStorage, K> {
+        let nodes = &self.tree.nodes;
+        let next = nodes.callback(|| leftmost_ending_after(nodes, self.tree.root, after.as_ref()));
+        Overlapping {
+            nodes: &self.tree.nodes,
+            next,
+            after,
+            before,
+        }
+    }
+
+    /// Largest end of all intervals
+    pub fn max_end(&self) -> Option<&K>
+    where
+        TStorage: InternalRefStorage,
+    {
+        let root = self.tree.root.get()?;
+        self.tree.nodes.get(root).summary.as_ref()
+    }
+
+    pub fn max_end_copied(&self) -> Option<K>
+    where
+        K: Copy,
+    {
+        let root = self.tree.root.get()?;
+        self.tree.nodes.get(root).summary
+    }
+}
+
+impl<TStorage: InternalStorage> IntervalTreeSet<TStorage> {
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn remove_at(
+        &mut self,
+        handle: NodeHandle,
+    ) -> Result<<TStorage as Storage>::Item, HandleError> {
+        self.tree.remove_at(handle)
+    }
+
+    pub fn get<'a>(&'a self, handle: NodeHandle) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.get(handle)
+    }
+
+    /// All intervals ordered by start
+    pub fn iter<'a>(&'a self) -> Iter<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.iter()
+    }
+}
+
+/// Iterator over the intervals ending after `after` and starting within `before`, ordered by start
+pub struct Overlapping<'a, TStorage, K> {
+    nodes: &'a TStorage,
+    next: OptionKey,
+    after: Option<K>,
+    before: Bound<K>,
+}
+
+/// Like [`Overlapping`], but yields clones of the intervals, so it works with shared storages as well
+pub struct OverlappingCloned<'a, TStorage, K>(Overlapping<'a, TStorage, K>);
+
+/// Leftmost node in the subtree of `index` ending after `after`, which every node does without `after`.
+/// Only descends into subtrees containing such a node, so it takes O(log n).
+/// Compares with `K`, so it has to run within [`InternalStorage::callback`].
+fn leftmost_ending_after<
+    K: Ord + Clone,
+    TStorage: InternalStorage<Item = Range<K>, Augment = MaxEnd>,
+>(
+    nodes: &TStorage,
+    mut index: OptionKey,
+    after: Option<&K>,
+) -> OptionKey {
+    // `None` is below all ends, whereas empty subtrees are summarized as `None`
+    let ends_after = |index: OptionKey| {
+        index
+            .get()
+            .is_some_and(|node_idx| nodes.get(node_idx).summary.as_ref() > after)
+    };
+    if !ends_after(index) {
+        return OptionKey::none();
+    }
+    loop {
+        let node_idx = index.unwrap();
+        let node = nodes.get(node_idx);
+        index = if ends_after(node.left) {
+            node.left
+        } else if Some(&node.value.end) > after {
+            return index;
+        } else {
+            node.right
+        };
+    }
+}
+
+impl<'a, K: Ord + Clone, TStorage: InternalStorage<Item = Range<K>, Augment = MaxEnd>>
+    Overlapping<'a, TStorage, K>
+{
+    /// Node of the next interval. Intervals are compared within a callback, as they might run user code.
+    fn next_node(&mut self) -> Option<usize> {
+        let node_idx = self.next.get()?;
+        let nodes = self.nodes;
+        let next = nodes.callback(|| {
+            let value = &nodes.get(node_idx).value;
+            let starts_within = match &self.before {
+                Bound::Included(before) => value.start <= *before,
+                Bound::Excluded(before) => value.start < *before,
+                Bound::Unbounded => true,
+            };
+            // All further intervals start even later
+            starts_within.then(|| self.next_after(node_idx))
+        });
+        self.next = next.unwrap_or_default();
+        next.map(|_| node_idx)
+    }
+
+    /// In-order successor of `node_idx` ending after `self.after`, skipping subtrees which end before
+    fn next_after(&self, node_idx: usize) -> OptionKey {
+        let after = self.after.as_ref();
+        let found = leftmost_ending_after(self.nodes, self.nodes.get(node_idx).right, after);
+        if found.get().is_some() {
+            return found;
+        }
+        let mut current = node_idx;
+        while let Some(parent_idx) = self.nodes.get(current).parent.get() {
+            let parent = self.nodes.get(parent_idx);
+            // Ascending from the left, the parent is next in order followed by its right subtree
+            if parent.left == current {
+                if Some(&parent.value.end) > after {
+                    return OptionKey::new(parent_idx);
+                }
+                let found = leftmost_ending_after(self.nodes, parent.right, after);
+                if found.get().is_some() {
+                    return found;
+                }
+            }
+            current = parent_idx;
+        }
+        OptionKey::none()
+    }
+}
+
+impl<'a, K: Ord + Clone + 'a, TStorage: InternalRefStorage<Item = Range<K>, Augment = MaxEnd>>
+    Iterator for Overlapping<'a, TStorage, K>
+{
+    type Item = &'a Range<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node_idx = self.next_node()?;
+        Some(&self.nodes.get(node_idx).value)
+    }
+}
+
+impl<'a, K: Ord + Clone + 'a, TStorage: InternalRefStorage<Item = Range<K>, Augment = MaxEnd>>
+    FusedIterator for Overlapping<'a, TStorage, K>
+{
+}
+
+impl<K: Ord + Clone, TStorage: InternalStorage<Item = Range<K>, Augment = MaxEnd>> Iterator
+    for OverlappingCloned<'_, TStorage, K>
+{
+    type Item = Range<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node_idx = self.0.next_node()?;
+        let nodes = self.0.nodes;
+        Some(nodes.callback(|| nodes.get(node_idx).value.clone()))
+    }
+}
+
+impl<K: Ord + Clone, TStorage: InternalStorage<Item = Range<K>, Augment = MaxEnd>> FusedIterator
+    for OverlappingCloned<'_, TStorage, K>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: &Range<u8>, b: &Range<u8>) -> bool {
+        a.start < b.end && b.start < a.end
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut set = IntervalTreeSet::new();
+        let mut intervals = Vec::new();
+        for x in 0..60u8 {
+            let start = x.wrapping_mul(37) % 50;
+            let interval = start..start + x.wrapping_mul(11) % 13;
+            if set.insert(interval.clone()).is_new() {
+                intervals.push(interval);
+            }
+        }
+        for interval in intervals.iter().step_by(4) {
+            assert_eq!(Some(interval.clone()), set.remove(interval));
+        }
+        intervals.retain(|x| set.contains(x));
+        intervals.sort_by_key(|x| (x.start, x.end));
+        set.tree.validate_constraints();
+        assert_eq!(intervals, set.iter_cloned().collect::<Vec<_>>());
+        assert_eq!(intervals.len(), set.len());
+        assert_eq!(
+            intervals.iter().map(|x| x.end).max().as_ref(),
+            set.max_end()
+        );
+
+        for start in 0..66 {
+            for end in start..66 {
+                let expected = intervals
+                    .iter()
+                    .filter(|x| overlaps(x, &(start..end)))
+                    .collect::<Vec<_>>();
+                assert_eq!(expected, set.overlapping(start..end).collect::<Vec<_>>());
+                assert!(expected
+                    .into_iter()
+                    .eq(&set.overlapping_cloned(start..end).collect::<Vec<_>>()));
+            }
+            let expected = intervals
+                .iter()
+                .filter(|x| x.contains(&start))
+                .collect::<Vec<_>>();
+            assert_eq!(expected, set.stabbing(start).collect::<Vec<_>>());
+            assert!(expected
+                .into_iter()
+                .eq(&set.stabbing_cloned(start).collect::<Vec<_>>()));
+        }
+    }
+
+    #[test]
+    fn time_ranges() {
+        let mut bookings = IntervalTreeSet::default();
+        bookings.insert(9..12);
+        bookings.insert(13..15);
+        bookings.insert(10..11);
+        assert!(!bookings.insert(9..12).is_new());
+
+        assert_eq!(
+            vec![&(9..12), &(10..11)],
+            bookings.overlapping(8..11).collect::<Vec<_>>()
+        );
+        assert_eq!(None, bookings.overlapping(12..13).next());
+        assert_eq!(vec![&(13..15)], bookings.stabbing(14).collect::<Vec<_>>());
+        assert_eq!(None, bookings.stabbing(15).next());
+        assert_eq!(Some(&15), bookings.max_end());
+
+        let handle = bookings.find(&(13..15)).unwrap();
+        assert_eq!(Ok(13..15), bookings.remove_at(handle));
+        assert_eq!(Some(12), bookings.max_end_copied());
+        assert_eq!(None, bookings.get(handle));
+    }
+
+    #[test]
+    fn shared_storage() {
+        let storage = SharedVecStorage::with_augment(MaxEnd);
+        let mut rooms = storage.add_empty_interval_tree();
+        let mut desks = storage.add_empty_interval_tree();
+        rooms.insert(9..12);
+        rooms.insert(13..15);
+        desks.insert(8..18);
+        rooms.insert(10..11);
+
+        assert_eq!(
+            vec![9..12, 10..11],
+            rooms.overlapping_cloned(8..11).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![13..15], rooms.stabbing_cloned(14).collect::<Vec<_>>());
+        assert_eq!(vec![8..18], desks.stabbing_cloned(14).collect::<Vec<_>>());
+        assert_eq!(
+            vec![9..12, 10..11, 13..15],
+            rooms.iter_cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(15), rooms.max_end_copied());
+        assert_eq!(Some(18), desks.max_end_copied());
+
+        let handle = rooms.find(&(13..15)).unwrap();
+        assert_eq!(Some(13..15), rooms.get_cloned(handle));
+        assert_eq!(None, desks.get_cloned(handle));
+        assert_eq!(Ok(13..15), rooms.remove_at(handle));
+        assert_eq!(Some(12), rooms.max_end_copied());
+        assert_eq!(None, rooms.get_cloned(handle));
+    }
+}
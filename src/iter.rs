@@ -1,69 +1,255 @@
 // Iter struct to allow in-order traversal
 
+use core::{
+    cmp::Ordering,
+    iter::FusedIterator,
+    ops::{Bound, RangeBounds},
+};
+
 use super::key::OptionKey;
 use super::storage::{InternalStorage, Storage};
-use super::RedBlackTreeSet;
+use super::{Compare, RedBlackTreeSet};
 
 pub struct Iter<'a, TStorage> {
-    tree: &'a RedBlackTreeSet<TStorage>,
-    next: OptionKey,
+    range: Range<'a, TStorage>,
+    remaining: usize,
 }
 
-impl<'a, TStorage: 'a + InternalStorage> RedBlackTreeSet<TStorage>
-where
-    <TStorage as Storage>::Item: 'a + Ord,
-{
+/// Double-ended iterator over the items within a range. `front` and `back` are both inclusive.
+pub struct Range<'a, TStorage> {
+    nodes: &'a TStorage,
+    front: OptionKey,
+    back: OptionKey,
+}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
     /// Safety: References musten't be accessible in safe code, if TStorage doesn't implement InternalRefStorage
     pub(crate) unsafe fn create_iterator(&self) -> Iter<'_, TStorage> {
-        // Start with the root if it exists and is active
-        let mut current = self.root;
-        while let Some(x) = self.nodes.get(current).left.get() {
-            current = x;
-        }
+        // Span from the leftmost to the rightmost node, if the tree isn't empty
+        let (front, back) = match (self.first_node(), self.last_node()) {
+            (Some(first), Some(last)) => (OptionKey::new(first), OptionKey::new(last)),
+            _ => (OptionKey::none(), OptionKey::none()),
+        };
 
         Iter {
-            tree: self,
-            next: OptionKey::new(current),
+            range: Range {
+                nodes: &self.nodes,
+                front,
+                back,
+            },
+            remaining: self.len,
         }
     }
 }
 
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeSet<TStorage, C>
+{
+    /// Safety: References musten't be accessible in safe code, if TStorage doesn't implement InternalRefStorage
+    pub(crate) unsafe fn create_range<Q: ?Sized>(
+        &self,
+        range: impl RangeBounds<Q>,
+    ) -> Range<'_, TStorage>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q> + Compare<Q>,
+    {
+        self.create_range_by(
+            range,
+            |start, end| self.compare.compare(start, end),
+            |node_idx, value| self.compare_node_value(node_idx, value),
+        )
+    }
+}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
+    /// Like [`RedBlackTreeSet::create_range`], but with bounds of any type the nodes can be compared with
+    ///
+    /// Safety: References musten't be accessible in safe code, if TStorage doesn't implement InternalRefStorage
+    pub(crate) unsafe fn create_range_by<Q: ?Sized>(
+        &self,
+        range: impl RangeBounds<Q>,
+        compare_bounds: impl Fn(&Q, &Q) -> Ordering,
+        compare_node: impl Fn(usize, &Q) -> Ordering,
+    ) -> Range<'_, TStorage> {
+        // Same panics as std::collections::BTreeSet::range
+        match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end))
+                if compare_bounds(start, end).is_eq() =>
+            {
+                panic!("range start and end are equal and excluded")
+            }
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) if compare_bounds(start, end).is_gt() => {
+                panic!("range start is greater than range end")
+            }
+            _ => {}
+        }
+
+        let mut front = self.lower_bound_by(range.start_bound(), &compare_node);
+        let mut back = self.upper_bound_by(range.end_bound(), &compare_node);
+        // Both bounds within the same gap between two items cross each other, so front is above the end
+        let is_empty = match (front.get(), range.end_bound()) {
+            (None, _) => true,
+            (Some(front), Bound::Included(end)) => compare_node(front, end).is_gt(),
+            (Some(front), Bound::Excluded(end)) => compare_node(front, end).is_ge(),
+            (Some(_), Bound::Unbounded) => false,
+        };
+        if is_empty {
+            front = OptionKey::none();
+            back = OptionKey::none();
+        }
+        Range {
+            nodes: &self.nodes,
+            front,
+            back,
+        }
+    }
+}
+
+impl<'a, TStorage: InternalStorage> Iter<'a, TStorage> {
+    /// Node the next call to `next` returns, without creating a reference to its value
+    pub(crate) fn peek_node(&self) -> Option<usize> {
+        self.range.front.get()
+    }
+
+    pub(crate) fn next_node(&mut self) -> Option<usize> {
+        let current = self.range.next_node()?;
+        self.remaining -= 1;
+        Some(current)
+    }
+
+    pub(crate) fn nodes(&self) -> &'a TStorage {
+        self.range.nodes
+    }
+}
+
+impl<'a, TStorage: InternalStorage> Range<'a, TStorage> {
+    fn next_node(&mut self) -> Option<usize> {
+        let current = self.front.get()?;
+        if self.back == current {
+            self.front = OptionKey::none();
+            self.back = OptionKey::none();
+        } else {
+            self.front = self.nodes.successor(current);
+        }
+        Some(current)
+    }
+}
+
 impl<'a, TStorage: 'a + InternalStorage> Iterator for Iter<'a, TStorage>
 where
-    <TStorage as Storage>::Item: Ord + 'a,
+    <TStorage as Storage>::Item: 'a,
 {
     type Item = &'a <TStorage as Storage>::Item;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut current = self.next.get()?;
-        let node = &self.tree.nodes.get(current);
+        let value = self.range.next()?;
+        self.remaining -= 1;
+        Some(value)
+    }
 
-        // Get the value
-        let value = &node.value;
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
 
-        // Prepare next node in the iteration
-        self.next = match node.right.get() {
-            Some(mut x) => {
-                while let Some(k) = &self.tree.nodes.get(x).left.get() {
-                    x = *k;
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        if n >= self.remaining {
+            self.range.front = OptionKey::none();
+            self.range.back = OptionKey::none();
+            self.remaining = 0;
+            return None;
+        }
+        let value = self.range.nth(n)?;
+        self.remaining -= n + 1;
+        Some(value)
+    }
+}
+
+impl<'a, TStorage: 'a + InternalStorage> DoubleEndedIterator for Iter<'a, TStorage>
+where
+    <TStorage as Storage>::Item: 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let value = self.range.next_back()?;
+        self.remaining -= 1;
+        Some(value)
+    }
+}
+
+impl<'a, TStorage: 'a + InternalStorage> ExactSizeIterator for Iter<'a, TStorage> where
+    <TStorage as Storage>::Item: 'a
+{
+}
+
+impl<'a, TStorage: 'a + InternalStorage> FusedIterator for Iter<'a, TStorage> where
+    <TStorage as Storage>::Item: 'a
+{
+}
+
+impl<'a, TStorage: 'a + InternalStorage> Iterator for Range<'a, TStorage>
+where
+    <TStorage as Storage>::Item: 'a,
+{
+    type Item = &'a <TStorage as Storage>::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next_node()?;
+        Some(&self.nodes.get(current).value)
+    }
+
+    /// Skips in O(log n) using the positions of front and back, if the augmentation counts items
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let front = self.front.get()?;
+        let positions = match n {
+            0 => None,
+            _ => self
+                .nodes
+                .position(front)
+                .zip(self.nodes.position(self.back.unwrap())),
+        };
+        match positions {
+            Some((front_position, back_position)) => {
+                if n > back_position - front_position {
+                    self.front = OptionKey::none();
+                    self.back = OptionKey::none();
+                    return None;
                 }
-                OptionKey::new(x)
+                let root = OptionKey::new(self.nodes.root_of(front));
+                self.front = self
+                    .nodes
+                    .select(root, front_position + n)
+                    .unwrap_or_default();
             }
             None => {
-                let mut parent = node.parent;
-                while let Some((k, parent_node)) = parent.get().map(|k| (k, self.tree.nodes.get(k)))
-                {
-                    if parent_node.right == current {
-                        current = k;
-                        parent = parent_node.parent;
-                    } else {
-                        break;
-                    }
+                for _ in 0..n {
+                    self.next()?;
                 }
-                parent
             }
-        };
+        }
+        self.next()
+    }
+}
 
-        Some(value)
+impl<'a, TStorage: 'a + InternalStorage> DoubleEndedIterator for Range<'a, TStorage>
+where
+    <TStorage as Storage>::Item: 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let current = self.back.get()?;
+        if self.front == current {
+            self.front = OptionKey::none();
+            self.back = OptionKey::none();
+        } else {
+            self.back = self.nodes.predecessor(current);
+        }
+        Some(&self.nodes.get(current).value)
     }
 }
+
+// Front and back stay none once they met
+impl<'a, TStorage: 'a + InternalStorage> FusedIterator for Range<'a, TStorage> where
+    <TStorage as Storage>::Item: 'a
+{
+}
@@ -0,0 +1,206 @@
+//! Joining and splitting trees within one storage. Both relink nodes in place, so handles stay valid.
+//!
+//! Trees are passed around as root and black height, which is the number of black nodes on each path
+//! from the root down, including the root. Tracking the heights keeps a split in O(log n).
+
+use crate::key::OptionKey;
+use crate::node::Color;
+use crate::storage::{InternalStorage, Storage};
+use crate::{Compare, RedBlackTreeSet};
+
+/// Root and black height of a tree
+pub(crate) type Subtree = (OptionKey, usize);
+
+/// Aborts if dropped while unwinding. Held where a panicking augmentation would leave nodes unreachable from any root.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        // Panicking during unwinding aborts the process
+        panic!("Augmentation panicked while splitting a tree");
+    }
+}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
+    pub(crate) fn black_height(&self, mut index: OptionKey) -> usize {
+        let mut height = 0;
+        while let Some(node_idx) = index.get() {
+            let node = self.nodes.get(node_idx);
+            height += (node.color == Color::Black) as usize;
+            index = node.left;
+        }
+        height
+    }
+
+    /// Joins the trees at `left` and `right` with the detached `pivot` in between and stores the result in `self.root`.
+    /// All items of `left` have to be ordered before `pivot` and all items of `right` after. Returns the black height.
+    ///
+    /// Descends the spine of the higher tree until the heights match, so it takes O(|left_height - right_height| + log n).
+    pub(crate) fn join(
+        &mut self,
+        (left, left_height): Subtree,
+        pivot: usize,
+        (right, right_height): Subtree,
+    ) -> usize {
+        // Both roots become black, so the pivot is the only possible red violation
+        let left_height = left_height + self.detach_root(left) as usize;
+        let right_height = right_height + self.detach_root(right) as usize;
+
+        let height = if left_height >= right_height {
+            let (mut parent, mut current, mut height) = (OptionKey::none(), left, left_height);
+            while height > right_height || self.is_red(current) {
+                let node = self.nodes.get(current.unwrap());
+                height -= (node.color == Color::Black) as usize;
+                parent = current;
+                current = node.right;
+            }
+            self.attach(pivot, current, right, parent);
+            match parent.get() {
+                Some(parent_idx) => {
+                    self.nodes.get_mut(parent_idx).right = OptionKey::new(pivot);
+                    self.root = left;
+                }
+                None => self.root = OptionKey::new(pivot),
+            }
+            left_height
+        } else {
+            let (mut parent, mut current, mut height) = (OptionKey::none(), right, right_height);
+            while height > left_height || self.is_red(current) {
+                let node = self.nodes.get(current.unwrap());
+                height -= (node.color == Color::Black) as usize;
+                parent = current;
+                current = node.left;
+            }
+            self.attach(pivot, left, current, parent);
+            // `right` is higher, so the pivot is never its new root
+            self.nodes.get_mut(parent.unwrap()).left = OptionKey::new(pivot);
+            self.root = right;
+            right_height
+        };
+
+        let height = height + self.insert_fixup(pivot) as usize;
+        self.nodes.update_path(OptionKey::new(pivot));
+        height
+    }
+
+    /// Turns the subtree at `root` into a standalone tree with a black root. Returns whether the root was red.
+    fn detach_root(&mut self, root: OptionKey) -> bool {
+        let Some(root_idx) = root.get() else {
+            return false;
+        };
+        let node = self.nodes.get_mut(root_idx);
+        node.parent = OptionKey::none();
+        let was_red = node.color == Color::Red;
+        node.color = Color::Black;
+        was_red
+    }
+
+    /// Makes `pivot` a red node with the given children below `parent`. The link from `parent` is set by the caller.
+    fn attach(&mut self, pivot: usize, left: OptionKey, right: OptionKey, parent: OptionKey) {
+        let node = self.nodes.get_mut(pivot);
+        node.color = Color::Red;
+        node.parent = parent;
+        node.left = left;
+        node.right = right;
+        for child in [left, right] {
+            if let Some(child_idx) = child.get() {
+                self.nodes.get_mut(child_idx).parent = OptionKey::new(pivot);
+            }
+        }
+    }
+
+    /// Puts the detached node `new` at the position of `old`, which is detached afterwards
+    pub(crate) fn substitute(&mut self, old: usize, new: usize) {
+        let old_node = self.nodes.get(old);
+        let (color, left, right) = (old_node.color, old_node.left, old_node.right);
+        self.transplant(old, OptionKey::new(new));
+        let node = self.nodes.get_mut(new);
+        node.color = color;
+        node.left = left;
+        node.right = right;
+        for child in [left, right] {
+            if let Some(child_idx) = child.get() {
+                self.nodes.get_mut(child_idx).parent = OptionKey::new(new);
+            }
+        }
+        // The summary of `new` might differ, even though it's ordered like `old`
+        self.nodes.update_path(OptionKey::new(new));
+    }
+
+    /// Splits the tree into the nodes `goes_left` accepts and the remaining ones. It has to accept a prefix of the nodes in order
+    /// and is called once per node on a path from the root. Returns both trees with their black heights; `self.root` is undefined afterwards.
+    ///
+    /// Descends to where the trees part and joins the subtrees hanging off that path on the way back up.
+    /// The heights of the joined trees grow along the path, so all joins take O(log n) together.
+    pub(crate) fn split_by(
+        &mut self,
+        mut goes_left: impl FnMut(&Self, usize) -> bool,
+    ) -> (Subtree, Subtree) {
+        let empty: Subtree = (OptionKey::none(), 0);
+        let Some(mut current) = self.root.get() else {
+            return (empty, empty);
+        };
+        let mut height = self.black_height(self.root);
+        let mut last_goes_left;
+        loop {
+            last_goes_left = goes_left(self, current);
+            let node = self.nodes.get(current);
+            let child = if last_goes_left {
+                node.right
+            } else {
+                node.left
+            };
+            let Some(child_idx) = child.get() else {
+                break;
+            };
+            height -= (node.color == Color::Black) as usize;
+            current = child_idx;
+        }
+
+        // Parents are read before joining, which relinks `current`. The path child tells where each node went.
+        // Until all joins are done, the nodes of `self` are spread over three trees.
+        let abort = AbortOnUnwind;
+        let (mut left, mut right) = (empty, empty);
+        let mut below = OptionKey::none();
+        loop {
+            let node = self.nodes.get(current);
+            let (parent, node_left, node_right) = (node.parent, node.left, node.right);
+            let child_height = height - (node.color == Color::Black) as usize;
+            let node_goes_left = match below.get() {
+                Some(below_idx) => node_right == below_idx,
+                None => last_goes_left,
+            };
+            if node_goes_left {
+                let joined_height = self.join((node_left, child_height), current, left);
+                left = (self.root, joined_height);
+            } else {
+                let joined_height = self.join(right, current, (node_right, child_height));
+                right = (self.root, joined_height);
+            }
+
+            let Some(parent_idx) = parent.get() else {
+                core::mem::forget(abort);
+                return (left, right);
+            };
+            height += (self.nodes.get(parent_idx).color == Color::Black) as usize;
+            below = OptionKey::new(current);
+            current = parent_idx;
+        }
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeSet<TStorage, C>
+{
+    /// Keeps the items below `key` and returns the root of the remaining ones, which are no longer part of `self`.
+    /// Counting them is up to the caller, which has to subtract them from `self.len`.
+    pub(crate) fn split_off_root<Q: ?Sized>(&mut self, key: &Q) -> OptionKey
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let ((left, _), (right, _)) =
+            self.split_by(|tree, node_idx| tree.compare_node_value(node_idx, key).is_lt());
+        self.root = left;
+        right
+    }
+}
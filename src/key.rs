@@ -1,10 +1,11 @@
 //! Option<usize> would be too inefficient... use magic-value usize::MAX for null
-//! This could only be achieved, if the vec contains usize::MAX elements, which is not possible, as removal just unlinks items
+//! This could only be achieved, if the vec contains usize::MAX elements, which is not possible, as removed slots are reused before the vec grows
 use core::fmt::Debug;
 
+// Type is public but only referenced in trait, which is sealed
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq)]
-pub(super) struct OptionKey(usize);
+pub struct OptionKey(usize);
 
 impl Debug for OptionKey {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -54,9 +55,10 @@ impl OptionKey {
         }
     }
 
+    /// Checked in release builds as well, so a broken tree panics instead of indexing with the null value
     #[inline(always)]
     pub const fn unwrap(&self) -> usize {
-        debug_assert!(self.0 != usize::MAX);
+        assert!(self.0 != usize::MAX, "Missing node");
         self.0
     }
 }
@@ -4,78 +4,605 @@
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
-use core::{cmp::Ordering, iter::Copied};
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    iter::Copied,
+    ops::{Bound, RangeBounds},
+};
 
 use key::OptionKey;
 use node::{Color, Node};
-use storage::{InternalRefStorage, InternalStorage, Storage};
+use storage::{InternalRefStorage, InternalStorage, Storage, Summary};
 
+mod augment;
+mod compare;
+mod cursor;
 #[cfg(any(feature = "fuzz", test))]
 mod fuzz;
+mod handle;
+mod interval;
 mod iter;
+#[cfg(feature = "alloc")]
+mod join;
 mod key;
+mod list;
+pub mod map;
+mod multiset;
 mod node;
+mod set_ops;
 mod storage;
 
 #[cfg(feature = "alloc")]
-pub use storage::SharedVecStorage;
+pub use storage::{MoveError, SharedVecStorage, VecStorage};
 
+pub use augment::{Augment, NoAugment, OrderStatistic, SubtreeSize};
+pub use compare::{Compare, OrdCompare, SortedByKey};
+pub use cursor::{Cursor, CursorMut, UnorderedError};
 #[cfg(any(feature = "fuzz", test))]
 pub use fuzz::*;
-pub use iter::Iter;
+pub use handle::{HandleError, Inserted, NodeHandle};
+pub use interval::{IntervalTreeSet, MaxEnd, Overlapping, OverlappingCloned};
+pub use iter::{Iter, Range};
+pub use list::RedBlackTreeList;
+pub use map::RedBlackTreeMap;
+pub use multiset::RedBlackTreeMultiSet;
+pub use set_ops::{Difference, Intersection, SymmetricDifference, Union};
 
-pub struct RedBlackTreeSet<TStorage> {
+pub struct RedBlackTreeSet<TStorage, C = OrdCompare> {
     nodes: TStorage,
-    root: usize,
+    root: OptionKey,
+    len: usize,
+    compare: C,
+}
+
+/// Nodes a fixup rotated down. A rotation hands the summary to the node moving up, as it covers the same items.
+/// The nodes moving down are recomputed once the tree is balanced again, so a panicking augmentation can't leave it
+/// half rebalanced. Fixups rotate at most three times.
+#[derive(Default)]
+struct Rotated {
+    nodes: [OptionKey; 3],
+    len: usize,
+}
+
+impl Rotated {
+    fn push(&mut self, node_idx: usize) {
+        self.nodes[self.len] = OptionKey::new(node_idx);
+        self.len += 1;
+    }
 }
 
 #[cfg(feature = "alloc")]
 impl<T: Ord> RedBlackTreeSet<storage::VecStorage<T>> {
-    pub fn new(value: T) -> Self {
+    pub fn new() -> Self {
+        Self::with_comparator(OrdCompare)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, C: Compare<T>> RedBlackTreeSet<storage::VecStorage<T>, C> {
+    /// Creates an empty tree ordered by `compare` instead of [`Ord`]
+    pub fn with_comparator(compare: C) -> Self {
+        Self::with_comparator_and_augment(compare, NoAugment)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Ord, A: Augment<T>> RedBlackTreeSet<storage::VecStorage<T, A>> {
+    /// Creates an empty tree whose nodes keep summaries of `augment`, e.g. [`SubtreeSize`] for [`RedBlackTreeSet::select`]
+    pub fn with_augment(augment: A) -> Self {
+        Self::with_comparator_and_augment(OrdCompare, augment)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: Augment<T>, C: Compare<T>> RedBlackTreeSet<storage::VecStorage<T, A>, C> {
+    pub fn with_comparator_and_augment(compare: C, augment: A) -> Self {
         RedBlackTreeSet {
-            nodes: storage::VecStorage::new_with(value),
-            root: 0,
+            nodes: storage::VecStorage::with_augment(augment),
+            root: OptionKey::none(),
+            len: 0,
+            compare,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: Augment<T> + Clone, C: Compare<T> + Clone>
+    RedBlackTreeSet<storage::VecStorage<T, A>, C>
+{
+    /// Splits off the items which aren't below `key`, like [`alloc::collections::BTreeSet::split_off`].
+    /// The tree is split in O(log n), but the returned part is moved into a new storage, which is linear in its length.
+    /// Handles of the moved items are invalidated: `self` reports them as stale and the returned tree rejects all handles from before the split.
+    pub fn split_off<Q: ?Sized>(&mut self, key: &Q) -> Self
+    where
+        C: Compare<T, Q>,
+    {
+        let root = self.split_off_root(key);
+        let mut split =
+            Self::with_comparator_and_augment(self.compare.clone(), self.nodes.augment().clone());
+        if let Some(root_idx) = root.get() {
+            let split_root = self
+                .nodes
+                .move_subtree(root_idx, &mut split.nodes, OptionKey::none());
+            split.root = OptionKey::new(split_root);
         }
+        // The new storage has no vacant slots, so its next index is the number of moved nodes
+        split.len = split.nodes.next_index();
+        self.len -= split.len;
+        split
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: Augment<T> + Default, C: Compare<T> + Default> Default
+    for RedBlackTreeSet<storage::VecStorage<T, A>, C>
+{
+    fn default() -> Self {
+        Self::with_comparator_and_augment(C::default(), A::default())
     }
 }
 
-impl<TStorage: InternalStorage> RedBlackTreeSet<TStorage>
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeSet<TStorage, C>
+{
+    /// Inserts `value`, unless an equal item exists. The existing item is kept in that case and `value` is dropped.
+    pub fn insert(&mut self, value: <TStorage as Storage>::Item) -> Inserted {
+        match self.locate(&value) {
+            Ok(existing_idx) => Inserted::Existing(self.handle(existing_idx)),
+            Err((parent, is_right)) => {
+                let node_idx = self.insert_leaf(value, parent, is_right);
+                Inserted::New(self.handle(node_idx))
+            }
+        }
+    }
+
+    /// Inserts `value` and returns the equal item it replaced. The node and its handle are reused for `value`.
+    pub fn replace(
+        &mut self,
+        value: <TStorage as Storage>::Item,
+    ) -> Option<<TStorage as Storage>::Item> {
+        match self.locate(&value) {
+            Ok(existing_idx) => {
+                let replaced =
+                    core::mem::replace(&mut self.nodes.get_mut(existing_idx).value, value);
+                // Equal items might still be summarized differently
+                self.nodes.update_path(OptionKey::new(existing_idx));
+                Some(replaced)
+            }
+            Err((parent, is_right)) => {
+                self.insert_leaf(value, parent, is_right);
+                None
+            }
+        }
+    }
+
+    /// Descends to the position of `value`. Returns the index of an equal node,
+    /// or the parent and side of the free child slot where `value` belongs (no parent for empty trees).
+    fn locate<Q: ?Sized>(&self, value: &Q) -> Result<usize, (OptionKey, bool)>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.locate_by(|node_idx| self.compare_node_value(node_idx, value))
+    }
+
+    /// Removes the item equal to `value` and returns it.
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> Option<<TStorage as Storage>::Item>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let node_idx = self.locate(value).ok()?;
+        Some(self.remove_node(node_idx))
+    }
+
+    fn compare_node_value<Q: ?Sized>(&self, node_idx: usize, value: &Q) -> Ordering
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let nodes = &self.nodes;
+        nodes.callback(|| self.compare.compare(&nodes.get(node_idx).value, value))
+    }
+
+    pub fn find<Q: ?Sized>(&self, value: &Q) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.locate(value)
+            .ok()
+            .map(|node_idx| self.handle(node_idx))
+    }
+
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.locate(value).is_ok()
+    }
+
+    /// First item which isn't below `bound`, like the start of [`RedBlackTreeSet::range`]
+    pub fn lower_bound<Q: ?Sized>(&self, bound: Bound<&Q>) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let node_idx = self.lower_bound_node(bound).get()?;
+        Some(self.handle(node_idx))
+    }
+
+    /// Last item which isn't above `bound`, like the end of [`RedBlackTreeSet::range`]
+    pub fn upper_bound<Q: ?Sized>(&self, bound: Bound<&Q>) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let node_idx = self.upper_bound_node(bound).get()?;
+        Some(self.handle(node_idx))
+    }
+
+    /// Greatest item less than or equal to `value`
+    pub fn floor<Q: ?Sized>(&self, value: &Q) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.upper_bound(Bound::Included(value))
+    }
+
+    /// Least item greater than or equal to `value`
+    pub fn ceiling<Q: ?Sized>(&self, value: &Q) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.lower_bound(Bound::Included(value))
+    }
+
+    pub fn range<'a, Q: ?Sized>(&'a self, range: impl RangeBounds<Q>) -> Range<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+        C: Compare<<TStorage as Storage>::Item, Q> + Compare<Q>,
+    {
+        unsafe { self.create_range(range) }
+    }
+
+    pub fn range_copied<'a, Q: ?Sized>(
+        &'a self,
+        range: impl RangeBounds<Q>,
+    ) -> Copied<Range<'a, TStorage>>
+    where
+        <TStorage as Storage>::Item: 'a + Copy,
+        C: Compare<<TStorage as Storage>::Item, Q> + Compare<Q>,
+    {
+        // Safety: Copied doesn't allow extraction of inner iterator
+        unsafe { self.create_range(range).copied() }
+    }
+
+    /// Combined summary of the items within `range`, computed from O(log n) subtree summaries
+    pub fn range_fold<Q: ?Sized>(&self, range: impl RangeBounds<Q>) -> Summary<TStorage>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.fold_range_by(range, |node_idx, value| {
+            self.compare_node_value(node_idx, value)
+        })
+    }
+
+    /// First node which isn't below `bound`
+    fn lower_bound_node<Q: ?Sized>(&self, bound: Bound<&Q>) -> OptionKey
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.lower_bound_by(bound, |node_idx, value| {
+            self.compare_node_value(node_idx, value)
+        })
+    }
+
+    /// Last node which isn't above `bound`
+    fn upper_bound_node<Q: ?Sized>(&self, bound: Bound<&Q>) -> OptionKey
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.upper_bound_by(bound, |node_idx, value| {
+            self.compare_node_value(node_idx, value)
+        })
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeSet<TStorage, C>
 where
-    <TStorage as Storage>::Item: Ord,
+    TStorage::Augment: OrderStatistic<<TStorage as Storage>::Item>,
 {
-    pub fn insert(&mut self, value: <TStorage as Storage>::Item) -> usize {
-        let new_node_idx = self.nodes.len();
-        let mut new_node = Node {
-            value,
-            color: Color::Red,
-            parent: OptionKey::none(),
-            left: OptionKey::none(),
-            right: OptionKey::none(),
-        };
+    /// Item at the 0-based position `index` in order, found in O(log n) by descending along subtree sizes
+    pub fn select(&self, index: usize) -> Option<NodeHandle> {
+        let node_idx = self.nodes.select(self.root, index).expect(COUNTED).get()?;
+        Some(self.handle(node_idx))
+    }
 
-        // If tree is empty, set as root and color black
+    /// Number of items below `value`, which is the position `value` has or would be inserted at
+    pub fn rank<Q: ?Sized>(&self, value: &Q) -> usize
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let mut rank = 0;
         let mut current = self.root;
+        while let Some(node_idx) = current.get() {
+            let node = self.nodes.get(node_idx);
+            if self.compare_node_value(node_idx, value).is_lt() {
+                rank += self.nodes.subtree_len(node.left).expect(COUNTED) + 1;
+                current = node.right;
+            } else {
+                current = node.left;
+            }
+        }
+        rank
+    }
+}
+
+const COUNTED: &str = "OrderStatistic augmentations count items";
+
+impl<TStorage: InternalStorage, K: ?Sized + Ord, F: Fn(&<TStorage as Storage>::Item) -> &K>
+    RedBlackTreeSet<TStorage, SortedByKey<F>>
+{
+    /// Like [`RedBlackTreeSet::find`], but only takes the key of the searched item
+    pub fn find_by_key<Q: ?Sized + Ord>(&self, key: &Q) -> Option<NodeHandle>
+    where
+        K: Borrow<Q>,
+    {
+        self.locate_by_key(key)
+            .ok()
+            .map(|node_idx| self.handle(node_idx))
+    }
+
+    /// Items with keys within `range`
+    pub fn range_by_key<'a, Q: ?Sized + Ord>(
+        &'a self,
+        range: impl RangeBounds<Q>,
+    ) -> Range<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+        K: Borrow<Q>,
+    {
+        unsafe {
+            self.create_range_by(range, Q::cmp, |node_idx, key| {
+                self.compare_node_key(node_idx, key)
+            })
+        }
+    }
+
+    /// Removes the item with an equal key and returns it
+    pub fn remove_by_key<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<<TStorage as Storage>::Item>
+    where
+        K: Borrow<Q>,
+    {
+        let node_idx = self.locate_by_key(key).ok()?;
+        Some(self.remove_node(node_idx))
+    }
+
+    fn locate_by_key<Q: ?Sized + Ord>(&self, key: &Q) -> Result<usize, (OptionKey, bool)>
+    where
+        K: Borrow<Q>,
+    {
+        self.locate_by(|node_idx| self.compare_node_key(node_idx, key))
+    }
+
+    fn compare_node_key<Q: ?Sized + Ord>(&self, node_idx: usize, key: &Q) -> Ordering
+    where
+        K: Borrow<Q>,
+    {
+        let nodes = &self.nodes;
+        nodes.callback(|| {
+            self.compare
+                .key(&nodes.get(node_idx).value)
+                .borrow()
+                .cmp(key)
+        })
+    }
+}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeSet<TStorage, C> {
+    /// Number of items in this tree, independent of other trees in the same storage
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Descends by comparing nodes with the target, like [`Ord::cmp`] of the node value with the target.
+    /// Returns the index of an equal node, or the parent and side of the free child slot (no parent for empty trees).
+    fn locate_by(
+        &self,
+        mut compare_node: impl FnMut(usize) -> Ordering,
+    ) -> Result<usize, (OptionKey, bool)> {
+        let Some(mut current) = self.root.get() else {
+            return Err((OptionKey::none(), false));
+        };
         loop {
-            let node = match self.compare_node_value(current, &new_node.value) {
-                Ordering::Less => &mut self.nodes.get_mut(current).right,
-                Ordering::Greater => &mut self.nodes.get_mut(current).left,
-                Ordering::Equal => {
-                    // If equal, we could either replace or keep existing
-                    // Here we're choosing to keep existing
-                    break current;
+            let (child, is_right) = match compare_node(current) {
+                Ordering::Less => (self.nodes.get(current).right, true),
+                Ordering::Greater => (self.nodes.get(current).left, false),
+                Ordering::Equal => return Ok(current),
+            };
+            match child.get() {
+                Some(child_idx) => current = child_idx,
+                None => return Err((OptionKey::new(current), is_right)),
+            }
+        }
+    }
+
+    /// First node which isn't below `bound`, where `compare_node` compares a node with the bound value
+    fn lower_bound_by<Q: ?Sized>(
+        &self,
+        bound: Bound<&Q>,
+        compare_node: impl Fn(usize, &Q) -> Ordering,
+    ) -> OptionKey {
+        let mut found = OptionKey::none();
+        let mut current = self.root;
+        while let Some(node_idx) = current.get() {
+            let is_below = match bound {
+                Bound::Included(value) => compare_node(node_idx, value).is_lt(),
+                Bound::Excluded(value) => compare_node(node_idx, value).is_le(),
+                Bound::Unbounded => false,
+            };
+            let node = self.nodes.get(node_idx);
+            if is_below {
+                current = node.right;
+            } else {
+                found = current;
+                current = node.left;
+            }
+        }
+        found
+    }
+
+    /// Summary of the nodes between the bounds. Descends to the topmost node within the range
+    /// and continues with one path towards each bound from there.
+    fn fold_range_by<Q: ?Sized>(
+        &self,
+        range: impl RangeBounds<Q>,
+        compare_node: impl Fn(usize, &Q) -> Ordering,
+    ) -> Summary<TStorage> {
+        let is_below = |node_idx, bound: Bound<&Q>| match bound {
+            Bound::Included(value) => compare_node(node_idx, value).is_lt(),
+            Bound::Excluded(value) => compare_node(node_idx, value).is_le(),
+            Bound::Unbounded => false,
+        };
+        let is_above = |node_idx, bound: Bound<&Q>| match bound {
+            Bound::Included(value) => compare_node(node_idx, value).is_gt(),
+            Bound::Excluded(value) => compare_node(node_idx, value).is_ge(),
+            Bound::Unbounded => false,
+        };
+        // Augmentations get references to items, so they run guarded
+        self.nodes.callback(|| {
+            let augment = self.nodes.augment();
+            let (start, end) = (range.start_bound(), range.end_bound());
+
+            let mut current = self.root;
+            let top = loop {
+                let Some(node_idx) = current.get() else {
+                    return augment.empty();
+                };
+                let node = self.nodes.get(node_idx);
+                current = if is_below(node_idx, start) {
+                    node.right
+                } else if is_above(node_idx, end) {
+                    node.left
+                } else {
+                    break node;
+                };
+            };
+
+            // Left of the top node, everything is below the end. Nodes not below the start are prepended with their right subtree.
+            let mut left = augment.empty();
+            let mut current = top.left;
+            while let Some(node_idx) = current.get() {
+                let node = self.nodes.get(node_idx);
+                if is_below(node_idx, start) {
+                    current = node.right;
+                } else {
+                    let mut summary = augment.single(&node.value);
+                    if let Some(right) = node.right.get() {
+                        summary = augment.combine(&summary, &self.nodes.get(right).summary);
+                    }
+                    left = augment.combine(&summary, &left);
+                    current = node.left;
+                }
+            }
+
+            // Mirrored on the right side
+            let mut right = augment.empty();
+            let mut current = top.right;
+            while let Some(node_idx) = current.get() {
+                let node = self.nodes.get(node_idx);
+                if is_above(node_idx, end) {
+                    current = node.left;
+                } else {
+                    let mut summary = augment.single(&node.value);
+                    if let Some(left) = node.left.get() {
+                        summary = augment.combine(&self.nodes.get(left).summary, &summary);
+                    }
+                    right = augment.combine(&right, &summary);
+                    current = node.right;
                 }
+            }
+
+            let summary = augment.combine(&left, &augment.single(&top.value));
+            augment.combine(&summary, &right)
+        })
+    }
+
+    /// Last node which isn't above `bound`, where `compare_node` compares a node with the bound value
+    fn upper_bound_by<Q: ?Sized>(
+        &self,
+        bound: Bound<&Q>,
+        compare_node: impl Fn(usize, &Q) -> Ordering,
+    ) -> OptionKey {
+        let mut found = OptionKey::none();
+        let mut current = self.root;
+        while let Some(node_idx) = current.get() {
+            let is_above = match bound {
+                Bound::Included(value) => compare_node(node_idx, value).is_gt(),
+                Bound::Excluded(value) => compare_node(node_idx, value).is_ge(),
+                Bound::Unbounded => false,
             };
-            if node.replace_if_none(new_node_idx) {
-                new_node.parent = OptionKey::new(current);
-                self.nodes.push(new_node);
-                self.insert_fixup(new_node_idx);
-                break new_node_idx;
+            let node = self.nodes.get(node_idx);
+            if is_above {
+                current = node.left;
+            } else {
+                found = current;
+                current = node.right;
             }
-            current = node.unwrap();
         }
+        found
+    }
+
+    /// Stores `value` in a new node and links it at the free child slot
+    fn insert_leaf(
+        &mut self,
+        value: <TStorage as Storage>::Item,
+        parent: OptionKey,
+        is_right: bool,
+    ) -> usize {
+        let new_node_idx = self.nodes.next_index();
+        let summary = self.nodes.callback(|| self.nodes.augment().single(&value));
+        self.nodes.push(Node::new(value, summary));
+        self.len += 1;
+        self.link(new_node_idx, parent, is_right);
+        new_node_idx
+    }
+
+    /// Attaches a detached node as a red leaf at the free child slot and rebalances
+    fn link(&mut self, node_idx: usize, parent: OptionKey, is_right: bool) {
+        let child = match parent.get() {
+            Some(parent_idx) => {
+                let parent_node = self.nodes.get_mut(parent_idx);
+                if is_right {
+                    &mut parent_node.right
+                } else {
+                    &mut parent_node.left
+                }
+            }
+            None => &mut self.root,
+        };
+        let is_free = child.replace_if_none(node_idx);
+        debug_assert!(is_free, "Child slot of {parent:?} is already taken");
+
+        let node = self.nodes.get_mut(node_idx);
+        node.color = Color::Red;
+        node.parent = parent;
+        node.left = OptionKey::none();
+        node.right = OptionKey::none();
+        self.insert_fixup(node_idx);
+        // Augmentations run once the tree is balanced, so a panic can't leave it broken
+        self.nodes.update_path(OptionKey::new(node_idx));
     }
 
-    fn insert_fixup(&mut self, mut node: usize) {
+    /// Restores the red-black constraints above the red `node`. Returns whether the root was recolored,
+    /// which adds a black node to all paths.
+    ///
+    /// Summaries on the path from `node` are left to the caller, whereas rotated nodes are recomputed here.
+    fn insert_fixup(&mut self, mut node: usize) -> bool {
+        let mut rotated = Rotated::default();
         while let Some(parent_idx) = self.nodes.get(node).parent.get() {
             //println!("Fixup {node}");
             // If parent is black, tree is valid
@@ -111,33 +638,342 @@ where
                 (true, true) => {
                     self.nodes.get_mut(parent_idx).color = Color::Black;
                     self.nodes.get_mut(grandparent_idx).color = Color::Red;
-                    self.rotate_left(grandparent_idx);
+                    self.rotate_left(grandparent_idx, &mut rotated);
                 }
                 (true, false) => {
                     node = parent_idx;
-                    self.rotate_right(node);
+                    self.rotate_right(node, &mut rotated);
                 }
                 (false, true) => {
                     node = parent_idx;
-                    self.rotate_left(node);
+                    self.rotate_left(node, &mut rotated);
                 }
                 (false, false) => {
                     self.nodes.get_mut(parent_idx).color = Color::Black;
                     self.nodes.get_mut(grandparent_idx).color = Color::Red;
-                    self.rotate_right(grandparent_idx);
+                    self.rotate_right(grandparent_idx, &mut rotated);
                 }
             }
         }
 
         // Ensure root is always black
-        self.nodes.get_mut(self.root).color = Color::Black;
+        let root = self.nodes.get_mut(self.root.unwrap());
+        let was_red = root.color == Color::Red;
+        root.color = Color::Black;
+        self.update_rotated(rotated);
+        was_red
+    }
+
+    /// Removes the node of `handle` and returns its value. Handles of other trees are rejected.
+    pub fn remove_at(
+        &mut self,
+        handle: NodeHandle,
+    ) -> Result<<TStorage as Storage>::Item, HandleError> {
+        let node_idx = self.check_handle(handle)?;
+        Ok(self.remove_node(node_idx))
+    }
+
+    fn handle(&self, node_idx: usize) -> NodeHandle {
+        let generation = self.nodes.generation(node_idx);
+        debug_assert!(generation.is_some(), "Node {node_idx} is vacant");
+        NodeHandle::new(node_idx, generation.unwrap_or_default(), self.nodes.id())
+    }
+
+    fn remove_node(&mut self, node_idx: usize) -> <TStorage as Storage>::Item {
+        self.len -= 1;
+        self.unlink(node_idx);
+        self.nodes.remove(node_idx).value
+    }
+
+    pub fn get<'a>(&'a self, handle: NodeHandle) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        let node_idx = self.check_handle(handle).ok()?;
+        Some(&self.nodes.get(node_idx).value)
+    }
+
+    pub fn get_copied(&self, handle: NodeHandle) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        let node_idx = self.check_handle(handle).ok()?;
+        Some(self.nodes.get(node_idx).value)
+    }
+
+    /// Whether the node of `handle` is part of this tree
+    pub fn contains_handle(&self, handle: NodeHandle) -> bool {
+        self.check_handle(handle).is_ok()
+    }
+
+    /// Smallest item. Like all min/max accessors, it descends to the leftmost node in O(log n)
+    pub fn first<'a>(&'a self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        Some(&self.nodes.get(self.first_node()?).value)
+    }
+
+    pub fn first_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        Some(self.nodes.get(self.first_node()?).value)
+    }
+
+    pub fn first_handle(&self) -> Option<NodeHandle> {
+        self.first_node().map(|node_idx| self.handle(node_idx))
+    }
+
+    /// Removes the smallest item, which makes the tree usable as an ordered work queue
+    pub fn pop_first(&mut self) -> Option<<TStorage as Storage>::Item> {
+        let node_idx = self.first_node()?;
+        Some(self.remove_node(node_idx))
+    }
+
+    /// Largest item
+    pub fn last<'a>(&'a self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        Some(&self.nodes.get(self.last_node()?).value)
+    }
+
+    pub fn last_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        Some(self.nodes.get(self.last_node()?).value)
+    }
+
+    pub fn last_handle(&self) -> Option<NodeHandle> {
+        self.last_node().map(|node_idx| self.handle(node_idx))
+    }
+
+    /// Removes the largest item
+    pub fn pop_last(&mut self) -> Option<<TStorage as Storage>::Item> {
+        let node_idx = self.last_node()?;
+        Some(self.remove_node(node_idx))
+    }
+
+    /// Item before the node of `handle` in order, walking the same links as [`Range`] does backwards
+    pub fn predecessor(&self, handle: NodeHandle) -> Result<Option<NodeHandle>, HandleError> {
+        let node_idx = self.check_handle(handle)?;
+        Ok(self
+            .nodes
+            .predecessor(node_idx)
+            .get()
+            .map(|node_idx| self.handle(node_idx)))
+    }
+
+    /// Item after the node of `handle` in order
+    pub fn successor(&self, handle: NodeHandle) -> Result<Option<NodeHandle>, HandleError> {
+        let node_idx = self.check_handle(handle)?;
+        Ok(self
+            .nodes
+            .successor(node_idx)
+            .get()
+            .map(|node_idx| self.handle(node_idx)))
+    }
+
+    fn first_node(&self) -> Option<usize> {
+        self.root.get().map(|root| self.nodes.leftmost(root))
+    }
+
+    fn last_node(&self) -> Option<usize> {
+        self.root.get().map(|root| self.nodes.rightmost(root))
+    }
+
+    /// Returns the node index of `handle`, if its node is part of this tree. Handles of other storages are detected by the storage id,
+    /// stale handles by the slot generation.
+    /// Trees sharing a storage have no per-node owner. Instead, the root reached by walking up from the node identifies the tree.
+    fn check_handle(&self, handle: NodeHandle) -> Result<usize, HandleError> {
+        if handle.storage() != self.nodes.id() {
+            return Err(HandleError::ForeignTree);
+        }
+        let node_idx = handle.index();
+        if self.nodes.generation(node_idx) != Some(handle.generation()) {
+            return Err(HandleError::Stale);
+        }
+        let mut current = node_idx;
+        while let Some(parent) = self.nodes.get(current).parent.get() {
+            current = parent;
+        }
+        if self.root == current {
+            Ok(node_idx)
+        } else {
+            Err(HandleError::ForeignTree)
+        }
+    }
+
+    /// Detaches the node from the tree and rebalances. The slot itself is left untouched.
+    fn unlink(&mut self, node_idx: usize) {
+        let node = self.nodes.get(node_idx);
+        let (parent, left, right, color) = (node.parent, node.left, node.right, node.color);
+
+        // `child` takes the place of the removed color. It might be none, so its parent is tracked separately
+        let (child, child_parent, removed_color) = match (left.get(), right.get()) {
+            (None, _) => {
+                self.transplant(node_idx, right);
+                (right, parent, color)
+            }
+            (Some(_), None) => {
+                self.transplant(node_idx, left);
+                (left, parent, color)
+            }
+            (Some(left_idx), Some(right_idx)) => {
+                // Relink the successor instead of swapping values, so indices stay stable
+                let mut successor = right_idx;
+                while let Some(x) = self.nodes.get(successor).left.get() {
+                    successor = x;
+                }
+                let successor_node = self.nodes.get(successor);
+                let (successor_parent, successor_right, successor_color) = (
+                    successor_node.parent,
+                    successor_node.right,
+                    successor_node.color,
+                );
+
+                let child_parent = if successor == right_idx {
+                    OptionKey::new(successor)
+                } else {
+                    self.transplant(successor, successor_right);
+                    self.nodes.get_mut(successor).right = right;
+                    self.nodes.get_mut(right_idx).parent = OptionKey::new(successor);
+                    successor_parent
+                };
+
+                self.transplant(node_idx, OptionKey::new(successor));
+                let successor_node = self.nodes.get_mut(successor);
+                successor_node.left = left;
+                successor_node.color = color;
+                self.nodes.get_mut(left_idx).parent = OptionKey::new(successor);
+                (successor_right, child_parent, successor_color)
+            }
+        };
+
+        if removed_color == Color::Black {
+            self.remove_fixup(child, child_parent);
+        }
+        // All subtrees which lost a node are on the path from `child_parent` to the root
+        self.nodes.update_path(child_parent);
+    }
+
+    /// Replaces the subtree at `node_idx` with the subtree at `replacement` in the parent of `node_idx`
+    fn transplant(&mut self, node_idx: usize, replacement: OptionKey) {
+        let parent = self.nodes.get(node_idx).parent;
+        match parent.get() {
+            Some(parent_idx) => {
+                let parent_node = self.nodes.get_mut(parent_idx);
+                if parent_node.left == node_idx {
+                    parent_node.left = replacement;
+                } else {
+                    parent_node.right = replacement;
+                }
+            }
+            None => self.root = replacement,
+        }
+        if let Some(replacement_idx) = replacement.get() {
+            self.nodes.get_mut(replacement_idx).parent = parent;
+        }
+    }
+
+    /// Summaries on the path from `parent` are left to the caller, whereas rotated nodes are recomputed here
+    fn remove_fixup(&mut self, mut node: OptionKey, mut parent: OptionKey) {
+        let mut rotated = Rotated::default();
+        while let Some(parent_idx) = parent.get() {
+            // A red node absorbs the missing black
+            if self.is_red(node) {
+                break;
+            }
+
+            // The sibling can't be none, as it's subtree contains at least one black node
+            let is_node_left = self.nodes.get(parent_idx).left == node;
+            if is_node_left {
+                let mut sibling_idx = self.nodes.get(parent_idx).right.unwrap();
+                if self.nodes.get(sibling_idx).color == Color::Red {
+                    self.nodes.get_mut(sibling_idx).color = Color::Black;
+                    self.nodes.get_mut(parent_idx).color = Color::Red;
+                    self.rotate_left(parent_idx, &mut rotated);
+                    sibling_idx = self.nodes.get(parent_idx).right.unwrap();
+                }
+
+                let sibling = self.nodes.get(sibling_idx);
+                let (sibling_left, sibling_right) = (sibling.left, sibling.right);
+                if !self.is_red(sibling_left) && !self.is_red(sibling_right) {
+                    self.nodes.get_mut(sibling_idx).color = Color::Red;
+                    node = parent;
+                    parent = self.nodes.get(parent_idx).parent;
+                    continue;
+                }
+
+                if !self.is_red(sibling_right) {
+                    self.nodes.get_mut(sibling_left.unwrap()).color = Color::Black;
+                    self.nodes.get_mut(sibling_idx).color = Color::Red;
+                    self.rotate_right(sibling_idx, &mut rotated);
+                    sibling_idx = self.nodes.get(parent_idx).right.unwrap();
+                }
+                self.nodes.get_mut(sibling_idx).color = self.nodes.get(parent_idx).color;
+                self.nodes.get_mut(parent_idx).color = Color::Black;
+                let sibling_right = self.nodes.get(sibling_idx).right.unwrap();
+                self.nodes.get_mut(sibling_right).color = Color::Black;
+                self.rotate_left(parent_idx, &mut rotated);
+            } else {
+                let mut sibling_idx = self.nodes.get(parent_idx).left.unwrap();
+                if self.nodes.get(sibling_idx).color == Color::Red {
+                    self.nodes.get_mut(sibling_idx).color = Color::Black;
+                    self.nodes.get_mut(parent_idx).color = Color::Red;
+                    self.rotate_right(parent_idx, &mut rotated);
+                    sibling_idx = self.nodes.get(parent_idx).left.unwrap();
+                }
+
+                let sibling = self.nodes.get(sibling_idx);
+                let (sibling_left, sibling_right) = (sibling.left, sibling.right);
+                if !self.is_red(sibling_left) && !self.is_red(sibling_right) {
+                    self.nodes.get_mut(sibling_idx).color = Color::Red;
+                    node = parent;
+                    parent = self.nodes.get(parent_idx).parent;
+                    continue;
+                }
+
+                if !self.is_red(sibling_left) {
+                    self.nodes.get_mut(sibling_right.unwrap()).color = Color::Black;
+                    self.nodes.get_mut(sibling_idx).color = Color::Red;
+                    self.rotate_left(sibling_idx, &mut rotated);
+                    sibling_idx = self.nodes.get(parent_idx).left.unwrap();
+                }
+                self.nodes.get_mut(sibling_idx).color = self.nodes.get(parent_idx).color;
+                self.nodes.get_mut(parent_idx).color = Color::Black;
+                let sibling_left = self.nodes.get(sibling_idx).left.unwrap();
+                self.nodes.get_mut(sibling_left).color = Color::Black;
+                self.rotate_right(parent_idx, &mut rotated);
+            }
+            node = self.root;
+            break;
+        }
+
+        if let Some(node_idx) = node.get() {
+            self.nodes.get_mut(node_idx).color = Color::Black;
+        }
+        self.update_rotated(rotated);
     }
 
-    fn compare_node_value(&self, node_idx: usize, value: &<TStorage as Storage>::Item) -> Ordering {
-        self.nodes.get(node_idx).value.cmp(value)
+    fn update_rotated(&mut self, rotated: Rotated) {
+        for node in rotated.nodes {
+            self.nodes.update_path(node);
+        }
+    }
+
+    /// Missing nodes count as black leafs
+    fn is_red(&self, node: OptionKey) -> bool {
+        node.get()
+            .is_some_and(|idx| self.nodes.get(idx).color == Color::Red)
     }
 
-    fn rotate_left(&mut self, node_idx: usize) {
+    fn rotate_left(&mut self, node_idx: usize, rotated: &mut Rotated) {
         // println!("Rotate left {node_idx}");
         let right_child_idx = self.nodes.get(node_idx).right.unwrap();
 
@@ -152,7 +988,7 @@ where
                 parent_node.right = OptionKey::new(right_child_idx);
             }
         } else {
-            self.root = right_child_idx;
+            self.root = OptionKey::new(right_child_idx);
         }
 
         // Rotate
@@ -162,9 +998,13 @@ where
         }
         self.nodes.get_mut(right_child_idx).left = OptionKey::new(node_idx);
         self.nodes.get_mut(node_idx).parent = OptionKey::new(right_child_idx);
+
+        // The former child now covers the items of `node_idx`, so it takes over the summary
+        self.nodes.swap_summaries(node_idx, right_child_idx);
+        rotated.push(node_idx);
     }
 
-    fn rotate_right(&mut self, node_idx: usize) {
+    fn rotate_right(&mut self, node_idx: usize, rotated: &mut Rotated) {
         //  println!("Rotate right");
         let left_child_idx = self.nodes.get(node_idx).left.unwrap();
 
@@ -178,7 +1018,7 @@ where
                 parent_node.left = OptionKey::new(left_child_idx);
             }
         } else {
-            self.root = left_child_idx;
+            self.root = OptionKey::new(left_child_idx);
         }
 
         // Rotate
@@ -188,6 +1028,49 @@ where
         }
         self.nodes.get_mut(left_child_idx).right = OptionKey::new(node_idx);
         self.nodes.get_mut(node_idx).parent = OptionKey::new(left_child_idx);
+
+        self.nodes.swap_summaries(node_idx, left_child_idx);
+        rotated.push(node_idx);
+    }
+
+    /// Cursor at the first item, or at the ghost position if the tree is empty
+    pub fn cursor_front(&self) -> Cursor<'_, TStorage, C> {
+        let mut cursor = self.cursor_with(OptionKey::none());
+        cursor.move_next();
+        cursor
+    }
+
+    /// Cursor at the last item, or at the ghost position if the tree is empty
+    pub fn cursor_back(&self) -> Cursor<'_, TStorage, C> {
+        let mut cursor = self.cursor_with(OptionKey::none());
+        cursor.move_prev();
+        cursor
+    }
+
+    /// Cursor at the node of `handle`
+    pub fn cursor_at(&self, handle: NodeHandle) -> Result<Cursor<'_, TStorage, C>, HandleError> {
+        let node_idx = self.check_handle(handle)?;
+        Ok(self.cursor_with(OptionKey::new(node_idx)))
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, TStorage, C> {
+        let mut cursor = self.cursor_mut_with(OptionKey::none());
+        cursor.move_next();
+        cursor
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, TStorage, C> {
+        let mut cursor = self.cursor_mut_with(OptionKey::none());
+        cursor.move_prev();
+        cursor
+    }
+
+    pub fn cursor_at_mut(
+        &mut self,
+        handle: NodeHandle,
+    ) -> Result<CursorMut<'_, TStorage, C>, HandleError> {
+        let node_idx = self.check_handle(handle)?;
+        Ok(self.cursor_mut_with(OptionKey::new(node_idx)))
     }
 
     pub fn iter<'a>(&'a self) -> Iter<'a, TStorage>
@@ -206,36 +1089,19 @@ where
         // Safety: Copied doesn't allow extraction of inner iterator
         unsafe { self.create_iterator().copied() }
     }
-
-    pub fn find(&self, value: &<TStorage as Storage>::Item) -> Option<usize> {
-        let mut current = self.root;
-
-        loop {
-            match self.compare_node_value(current, value) {
-                Ordering::Equal => {
-                    return Some(current);
-                }
-                Ordering::Less => {
-                    current = self.nodes.get(current).right.get()?;
-                }
-                Ordering::Greater => {
-                    current = self.nodes.get(current).left.get()?;
-                }
-            }
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use fuzz::{build_fuzz_tree, fuzz_insert};
+    use fuzz::{build_fuzz_tree, fuzz_insert, fuzz_remove};
 
     use super::node::Node;
     use super::*;
 
     #[test]
     fn rotate_right() {
-        let mut tree = RedBlackTreeSet::new(15);
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(15);
 
         // Insert some values
         tree.insert(5);
@@ -267,7 +1133,8 @@ mod tests {
 
     #[test]
     fn complex() {
-        let mut tree = RedBlackTreeSet::new(5);
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(5);
         tree.insert(8);
         tree.insert(9);
         tree.insert(12);
@@ -284,6 +1151,7 @@ mod tests {
                 &Node {
                     color: Color::Black,
                     value: 5,
+                    summary: (),
                     parent: OptionKey::new(1),
                     left: OptionKey::none(),
                     right: OptionKey::none(),
@@ -291,6 +1159,7 @@ mod tests {
                 &Node {
                     color: Color::Red,
                     value: 8,
+                    summary: (),
                     parent: OptionKey::new(3),
                     left: OptionKey::new(0),
                     right: OptionKey::new(2),
@@ -298,6 +1167,7 @@ mod tests {
                 &Node {
                     color: Color::Black,
                     value: 9,
+                    summary: (),
                     parent: OptionKey::new(1),
                     left: OptionKey::none(),
                     right: OptionKey::new(8),
@@ -305,6 +1175,7 @@ mod tests {
                 &Node {
                     color: Color::Black,
                     value: 12,
+                    summary: (),
                     parent: OptionKey::none(),
                     left: OptionKey::new(1),
                     right: OptionKey::new(5),
@@ -312,6 +1183,7 @@ mod tests {
                 &Node {
                     color: Color::Black,
                     value: 13,
+                    summary: (),
                     parent: OptionKey::new(5),
                     left: OptionKey::none(),
                     right: OptionKey::none(),
@@ -319,6 +1191,7 @@ mod tests {
                 &Node {
                     color: Color::Red,
                     value: 15,
+                    summary: (),
                     parent: OptionKey::new(3),
                     left: OptionKey::new(4),
                     right: OptionKey::new(6),
@@ -326,6 +1199,7 @@ mod tests {
                 &Node {
                     color: Color::Black,
                     value: 19,
+                    summary: (),
                     parent: OptionKey::new(5),
                     left: OptionKey::none(),
                     right: OptionKey::new(7),
@@ -333,6 +1207,7 @@ mod tests {
                 &Node {
                     color: Color::Red,
                     value: 23,
+                    summary: (),
                     parent: OptionKey::new(6),
                     left: OptionKey::none(),
                     right: OptionKey::none(),
@@ -340,6 +1215,7 @@ mod tests {
                 &Node {
                     color: Color::Red,
                     value: 10,
+                    summary: (),
                     parent: OptionKey::new(2),
                     left: OptionKey::none(),
                     right: OptionKey::none(),
@@ -365,4 +1241,613 @@ mod tests {
         build_fuzz_tree::<true>(&[37, 1, 0, 219]);
         fuzz_insert(&[37, 1, 0, 219]);
     }
+
+    #[test]
+    fn remove_inner_nodes() {
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(5);
+        for x in [8, 9, 12, 13, 15, 19, 23, 10] {
+            tree.insert(x);
+        }
+        let idx_of_15 = tree.find(&15).unwrap();
+
+        assert_eq!(Some(12), tree.remove(&12));
+        tree.validate_constraints();
+        assert_eq!(Some(8), tree.remove(&8));
+        tree.validate_constraints();
+        assert_eq!(None, tree.remove(&8));
+
+        assert_eq!(Some(idx_of_15), tree.find(&15));
+        assert_eq!(
+            vec![5, 9, 10, 13, 15, 19, 23],
+            tree.iter_copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn remove_last_item() {
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(1);
+        let idx = tree.insert(2).handle();
+        assert_eq!(Ok(2), tree.remove_at(idx));
+        assert_eq!(Some(1), tree.remove(&1));
+        assert_eq!(None, tree.remove(&1));
+        tree.validate_constraints();
+        assert_eq!(None, tree.iter_copied().next());
+
+        tree.insert(3);
+        assert_eq!(vec![3], tree.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tree = RedBlackTreeSet::<storage::VecStorage<u8>>::default();
+        tree.validate_constraints();
+        assert_eq!(None, tree.find(&1));
+        assert_eq!(None, tree.iter().next());
+
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_empty_tree();
+        assert_eq!(None, tree.iter_copied().next());
+        tree.insert(1);
+        assert_eq!(vec![1], tree.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn remove_from_shared_storage() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_tree(1);
+        let mut tree2 = storage.add_tree(2);
+        tree.insert(3);
+        tree2.insert(3);
+
+        assert_eq!(Some(3), tree.remove(&3));
+        assert_eq!(vec![1], tree.iter_copied().collect::<Vec<_>>());
+        assert_eq!(vec![2, 3], tree2.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reuse_removed_slots() {
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(1);
+        let idx_2 = tree.insert(2).handle();
+        let idx_3 = tree.insert(3).handle();
+        let idx_4 = tree.insert(4).handle();
+        tree.remove_at(idx_2).unwrap();
+        tree.remove_at(idx_3).unwrap();
+
+        assert_eq!(idx_3.index(), tree.insert(5).handle().index());
+        assert_eq!(idx_2.index(), tree.insert(6).handle().index());
+        assert_eq!(idx_4.index() + 1, tree.insert(7).handle().index());
+        tree.validate_constraints();
+        assert_eq!(vec![1, 4, 5, 6, 7], tree.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn remove_all() {
+        fuzz_remove(&[37, 1, 0, 219, 4, 8, 15, 16, 23, 42, 7, 7, 99, 100]);
+        fuzz_remove(&(0..=255).collect::<Vec<_>>());
+        fuzz_remove(&(0..=255).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn move_between_trees() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_tree(10);
+        let mut tree2 = storage.add_tree(20);
+        let indices = (0..10).map(|x| tree.insert(x).handle()).collect::<Vec<_>>();
+        tree2.insert(5);
+
+        for &idx in indices.iter().step_by(2) {
+            tree.move_to(idx, &mut tree2).unwrap();
+            tree.validate_constraints();
+            tree2.validate_constraints();
+        }
+        assert_eq!(Some(indices[4]), tree2.find(&4));
+        assert_eq!(
+            vec![1, 3, 5, 7, 9, 10],
+            tree.iter_copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 2, 4, 5, 6, 8, 20],
+            tree2.iter_copied().collect::<Vec<_>>()
+        );
+
+        let idx_of_5 = tree2.find(&5).unwrap();
+        assert_eq!(
+            Err(MoveError::Occupied(idx_of_5)),
+            tree.move_to(indices[5], &mut tree2)
+        );
+
+        let mut tree3 = storage.add_tree(30);
+        let idx_of_30 = tree3.find(&30).unwrap();
+        tree3.move_to(idx_of_30, &mut tree).unwrap();
+        assert_eq!(None, tree3.iter_copied().next());
+        assert_eq!(Some(idx_of_30), tree.find(&30));
+    }
+
+    #[test]
+    fn append_trees() {
+        let storage = storage::SharedVecStorage::new();
+        let build = |range: core::ops::Range<u32>| {
+            let mut tree = storage.add_empty_tree();
+            let handles = range.map(|x| tree.insert(x).handle()).collect::<Vec<_>>();
+            (tree, handles)
+        };
+
+        // Disjoint ranges are joined on either side
+        let (mut tree, _) = build(0..50);
+        let (high, high_handles) = build(50..60);
+        assert!(tree.append(high).is_ok());
+        tree.validate_constraints();
+        let (mut low, _) = build(100..300);
+        let (lower, lower_handles) = build(60..100);
+        assert!(low.append(lower).is_ok());
+        low.validate_constraints();
+        assert!(tree.append(low).is_ok());
+        tree.validate_constraints();
+        assert_eq!(300, tree.len());
+        assert_eq!(
+            (0..300).collect::<Vec<_>>(),
+            tree.iter_copied().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(high_handles[3]), tree.find(&53));
+        assert_eq!(Some(lower_handles[0]), tree.find(&60));
+
+        // Overlapping items are relinked one by one and duplicates are dropped
+        let (mut odd, _) = build(0..0);
+        for x in (1..40).step_by(2) {
+            odd.insert(x);
+        }
+        let (mut tree, _) = build(10..30);
+        assert!(tree.append(odd).is_ok());
+        tree.validate_constraints();
+        let expected = (0..40)
+            .filter(|x| x % 2 == 1 || (10..30).contains(x))
+            .collect::<Vec<_>>();
+        assert_eq!(expected.len(), tree.len());
+        assert_eq!(expected, tree.iter_copied().collect::<Vec<_>>());
+
+        let (mut empty, _) = build(0..0);
+        assert!(empty.append(tree).is_ok());
+        assert_eq!(expected, empty.iter_copied().collect::<Vec<_>>());
+        assert!(empty.append(build(0..0).0).is_ok());
+        assert_eq!(expected.len(), empty.len());
+    }
+
+    #[test]
+    fn append_larger_overlapping_tree() {
+        let storage = storage::SharedVecStorage::new();
+        let by_key = SortedByKey::new(|x: &(u32, char)| &x.0);
+        let mut small = storage.add_empty_tree_with_comparator(by_key);
+        let handles = (0..20)
+            .map(|x| small.insert((x * 5, 's')).handle())
+            .collect::<Vec<_>>();
+        let mut large = storage.add_empty_tree_with_comparator(by_key);
+        for x in 0..200 {
+            large.insert((x, 'l'));
+        }
+
+        // The small tree is relinked into the large one, but its items still win
+        assert!(small.append(large).is_ok());
+        small.validate_constraints();
+        assert_eq!(200, small.len());
+        let expected = (0..200)
+            .map(|x| (x, if x % 5 == 0 && x < 100 { 's' } else { 'l' }))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, small.iter_copied().collect::<Vec<_>>());
+        for (x, handle) in handles.into_iter().enumerate() {
+            assert_eq!(Some(handle), small.find(&(x as u32 * 5, ' ')));
+        }
+    }
+
+    #[test]
+    fn append_rejects_differently_ordered_tree() {
+        fn ordered(descending: bool) -> impl Fn(&u32, &u32) -> core::cmp::Ordering + Copy {
+            move |a: &u32, b: &u32| if descending { b.cmp(a) } else { a.cmp(b) }
+        }
+        let storage = storage::SharedVecStorage::new();
+        let mut ascending = storage.add_empty_tree_with_comparator(ordered(false));
+        let mut descending = storage.add_empty_tree_with_comparator(ordered(true));
+        for x in 0..10 {
+            ascending.insert(x);
+            descending.insert(x + 10);
+        }
+
+        // The rejected tree is handed back untouched
+        let descending = ascending.append(descending).err().unwrap();
+        descending.validate_constraints();
+        assert_eq!(
+            (10..20).rev().collect::<Vec<_>>(),
+            descending.iter_copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (0..10).collect::<Vec<_>>(),
+            ascending.iter_copied().collect::<Vec<_>>()
+        );
+        let mut empty = storage.add_empty_tree_with_comparator(ordered(false));
+        assert!(empty.append(descending).is_err());
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn split_off_at_key() {
+        let storage = storage::SharedVecStorage::with_augment(SubtreeSize);
+        for split_key in 0..=40 {
+            let mut owned = RedBlackTreeSet::new();
+            let mut shared = storage.add_empty_tree();
+            let handles = (0..40)
+                .map(|x| {
+                    owned.insert(x);
+                    shared.insert(x).handle()
+                })
+                .collect::<Vec<_>>();
+
+            let shared_split = shared.split_off(&split_key);
+            let owned_split = owned.split_off(&split_key);
+            for tree in [&shared, &shared_split] {
+                tree.validate_constraints();
+            }
+            for tree in [&owned, &owned_split] {
+                tree.validate_constraints();
+            }
+            assert_eq!(split_key, shared.len());
+            assert_eq!(40 - split_key, shared_split.len());
+            assert_eq!(40 - split_key, owned_split.len());
+            assert_eq!(
+                (split_key..40).collect::<Vec<_>>(),
+                shared_split.iter_copied().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                (0..split_key).collect::<Vec<_>>(),
+                owned.iter_copied().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                (split_key..40).collect::<Vec<_>>(),
+                owned_split.iter().copied().collect::<Vec<_>>()
+            );
+            for (x, handle) in handles.into_iter().enumerate() {
+                let tree = if x < split_key {
+                    &shared
+                } else {
+                    &shared_split
+                };
+                assert_eq!(Some(x), tree.get_copied(handle));
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_owned_invalidates_moved_handles() {
+        let mut tree = RedBlackTreeSet::new();
+        let handles = (0..10).map(|x| tree.insert(x).handle()).collect::<Vec<_>>();
+        let mut tail = tree.split_off(&5);
+        for (x, handle) in handles.into_iter().enumerate() {
+            assert_eq!(Err(HandleError::ForeignTree), tail.check_handle(handle));
+            if x < 5 {
+                assert_eq!(Some(x), tree.get_copied(handle));
+            } else {
+                assert_eq!(Err(HandleError::Stale), tree.check_handle(handle));
+            }
+        }
+        let handle = tail.insert(10).handle();
+        assert_eq!(Some(10), tail.get_copied(handle));
+        assert_eq!(Err(HandleError::ForeignTree), tree.check_handle(handle));
+    }
+
+    #[test]
+    fn partition_by_deadline() {
+        let storage = storage::SharedVecStorage::with_augment(SubtreeSize);
+        let mut queue = storage.add_empty_tree();
+        for deadline in [30, 10, 50, 20, 40, 25] {
+            queue.insert(deadline);
+        }
+        let mut late = queue.split_off(&25);
+        assert_eq!(vec![10, 20], queue.iter_copied().collect::<Vec<_>>());
+        assert_eq!(4, late.len());
+        assert_eq!(Some(40), late.select(2).and_then(|x| late.get_copied(x)));
+        assert!(queue.append(late.split_off(&45)).is_ok());
+        assert_eq!(vec![10, 20, 50], queue.iter_copied().collect::<Vec<_>>());
+        assert_eq!(vec![25, 30, 40], late.iter_copied().collect::<Vec<_>>());
+        queue.validate_constraints();
+        late.validate_constraints();
+    }
+
+    #[test]
+    fn range() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in (0..20).step_by(2) {
+            tree.insert(x);
+        }
+
+        assert_eq!(
+            vec![4, 6, 8],
+            tree.range(3..10).copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![4, 6, 8, 10],
+            tree.range(4..=10).copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![16, 18],
+            tree.range((Bound::Excluded(14), Bound::Unbounded))
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![0, 2], tree.range_copied(..4).collect::<Vec<_>>());
+        assert_eq!(10, tree.range(..).count());
+        assert_eq!(None, tree.range(5..6).next());
+        assert_eq!(None, tree.range(7..=7).next());
+        assert_eq!(None, tree.range(20..).next());
+
+        let mut range = tree.range(2..=8);
+        assert_eq!(Some(&8), range.next_back());
+        assert_eq!(Some(&2), range.next());
+        assert_eq!(Some(&6), range.next_back());
+        assert_eq!(Some(&4), range.next());
+        assert_eq!(None, range.next_back());
+        assert_eq!(None, range.next());
+    }
+
+    #[test]
+    #[should_panic(expected = "range start is greater than range end")]
+    fn range_start_greater_than_end() {
+        let mut tree = RedBlackTreeSet::new();
+        tree.insert(1);
+        tree.range((Bound::Included(3), Bound::Excluded(2)));
+    }
+
+    #[test]
+    fn range_in_shared_storage() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_empty_tree();
+        let mut tree2 = storage.add_empty_tree();
+        for x in 0..10 {
+            tree.insert(x);
+            tree2.insert(x * 10);
+        }
+        assert_eq!(
+            vec![7, 6, 5],
+            tree.range_copied(5..8).rev().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![50, 60, 70],
+            tree2.range_copied(50..=70).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn iterate_from_both_ends() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in [5, 3, 8, 1, 4, 7, 9, 2, 6] {
+            tree.insert(x);
+        }
+        assert_eq!(
+            vec![9, 8, 7],
+            tree.iter().rev().take(3).copied().collect::<Vec<_>>()
+        );
+
+        let mut iter = tree.iter_copied();
+        assert_eq!(Some(1), iter.next());
+        assert_eq!(Some(9), iter.next_back());
+        assert_eq!(vec![2, 3, 4, 5, 6, 7, 8], iter.by_ref().collect::<Vec<_>>());
+        assert_eq!(None, iter.next_back());
+        assert_eq!(None, iter.next());
+
+        let empty = RedBlackTreeSet::<storage::VecStorage<u8>>::new();
+        assert_eq!(None, empty.iter().next_back());
+    }
+
+    #[test]
+    fn len_per_tree() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_tree(0);
+        let mut tree2 = storage.add_empty_tree();
+        assert!(tree2.is_empty());
+        let idx = tree.insert(1).handle();
+        tree.insert(1);
+        tree2.insert(2);
+        assert_eq!((2, 1), (tree.len(), tree2.len()));
+
+        tree.move_to(idx, &mut tree2).unwrap();
+        assert_eq!((1, 2), (tree.len(), tree2.len()));
+        tree.remove(&0);
+        assert!(tree.is_empty());
+
+        let mut iter = tree2.iter_copied();
+        assert_eq!(2, iter.len());
+        iter.next_back();
+        assert_eq!(1, iter.len());
+    }
+
+    #[test]
+    fn find_by_borrowed_value() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in ["b", "a", "c"] {
+            tree.insert(String::from(x));
+        }
+        assert!(tree.contains("a"));
+        assert!(!tree.contains("d"));
+        assert_eq!(tree.find("c"), tree.find(&String::from("c")));
+        assert_eq!(
+            vec!["b", "c"],
+            tree.range::<str>((Bound::Included("b"), Bound::Unbounded))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(String::from("b")), tree.remove("b"));
+        assert_eq!(vec!["a", "c"], tree.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn handles() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_empty_tree();
+        let mut tree2 = storage.add_empty_tree();
+        let handle = tree.insert(1).handle();
+        let handle2 = tree2.insert(2).handle();
+
+        assert_eq!(Some(handle), tree.find(&1));
+        assert_eq!(Some(1), tree.get_copied(handle));
+        assert!(tree.contains_handle(handle));
+        assert!(!tree.contains_handle(handle2));
+        assert!(!tree.contains_handle(NodeHandle::new(100, 0, handle.storage())));
+        assert_eq!(None, tree.get_copied(handle2));
+        assert_eq!(Err(HandleError::ForeignTree), tree.remove_at(handle2));
+        assert_eq!(
+            Err(MoveError::Handle(HandleError::ForeignTree)),
+            tree.move_to(handle2, &mut tree2)
+        );
+
+        tree.move_to(handle, &mut tree2).unwrap();
+        assert!(tree2.contains_handle(handle));
+        assert_eq!(Ok(1), tree2.remove_at(handle));
+        assert_eq!(Err(HandleError::Stale), tree2.remove_at(handle));
+        assert!(!tree2.contains_handle(handle));
+
+        let mut owned = RedBlackTreeSet::new();
+        let handle = owned.insert("a").handle();
+        assert_eq!(Some(&"a"), owned.get(handle));
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut tree = RedBlackTreeSet::new();
+        let handle = tree.insert(1).handle();
+        tree.remove(&1);
+        assert_eq!(None, tree.get(handle));
+
+        // Reuses the slot of the removed node
+        let new_handle = tree.insert(2).handle();
+        assert_eq!(handle.index(), new_handle.index());
+        assert!(!tree.contains_handle(handle));
+        assert_eq!(None, tree.get(handle));
+        assert_eq!(Err(HandleError::Stale), tree.remove_at(handle));
+        assert_eq!(Some(&2), tree.get(new_handle));
+    }
+
+    #[test]
+    fn insert_and_replace_report_existing_items() {
+        let mut tree = RedBlackTreeSet::new();
+        let handle = tree.insert(1).handle();
+        assert!(tree.insert(2).is_new());
+        assert_eq!(Inserted::Existing(handle), tree.insert(1));
+
+        assert_eq!(None, tree.replace(3));
+        assert_eq!(Some(1), tree.replace(1));
+        assert!(tree.contains_handle(handle));
+        assert_eq!(vec![1, 2, 3], tree.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn first_and_last() {
+        let storage = storage::SharedVecStorage::new();
+        let mut tree = storage.add_empty_tree();
+        assert_eq!(None, tree.first_copied());
+        assert_eq!(None, tree.pop_last());
+
+        for x in [5, 3, 8, 1, 9, 7] {
+            tree.insert(x);
+        }
+        assert_eq!(Some(1), tree.first_copied());
+        assert_eq!(Some(9), tree.last_copied());
+        assert_eq!(tree.find(&9), tree.last_handle());
+
+        assert_eq!(Some(1), tree.pop_first());
+        assert_eq!(Some(9), tree.pop_last());
+        assert_eq!(tree.find(&3), tree.first_handle());
+        assert_eq!(4, tree.len());
+        tree.validate_constraints();
+
+        let mut owned = RedBlackTreeSet::new();
+        owned.insert("b");
+        owned.insert("a");
+        assert_eq!(Some(&"a"), owned.first());
+        assert_eq!(Some(&"b"), owned.last());
+    }
+
+    #[test]
+    fn nearest_items() {
+        let mut tree = RedBlackTreeSet::new();
+        for x in [10, 20, 30, 40] {
+            tree.insert(x);
+        }
+        let get = |handle: Option<NodeHandle>| handle.and_then(|h| tree.get_copied(h));
+        assert_eq!(Some(20), get(tree.floor(&25)));
+        assert_eq!(Some(20), get(tree.floor(&20)));
+        assert_eq!(None, get(tree.floor(&5)));
+        assert_eq!(Some(30), get(tree.ceiling(&25)));
+        assert_eq!(Some(30), get(tree.ceiling(&30)));
+        assert_eq!(None, get(tree.ceiling(&45)));
+
+        assert_eq!(Some(30), get(tree.lower_bound(Bound::Excluded(&20))));
+        assert_eq!(Some(10), get(tree.lower_bound(Bound::Unbounded)));
+        assert_eq!(Some(10), get(tree.upper_bound(Bound::Excluded(&20))));
+        assert_eq!(Some(40), get(tree.upper_bound(Bound::Unbounded)));
+
+        let handle = tree.find(&20).unwrap();
+        assert_eq!(Ok(tree.find(&10)), tree.predecessor(handle));
+        assert_eq!(Ok(tree.find(&30)), tree.successor(handle));
+        assert_eq!(Ok(None), tree.predecessor(tree.first_handle().unwrap()));
+        assert_eq!(Ok(None), tree.successor(tree.last_handle().unwrap()));
+
+        tree.remove(&20);
+        assert_eq!(Err(HandleError::Stale), tree.successor(handle));
+    }
+
+    #[test]
+    fn order_statistics() {
+        let mut tree = RedBlackTreeSet::with_augment(SubtreeSize);
+        for x in (0..100).rev() {
+            tree.insert(x * 2);
+        }
+        for x in (0..100).filter(|x| x % 3 == 0) {
+            tree.remove(&(x * 2));
+        }
+        tree.validate_constraints();
+
+        let expected = (0..100)
+            .filter(|x| x % 3 != 0)
+            .map(|x| x * 2)
+            .collect::<Vec<_>>();
+        for (i, x) in expected.iter().enumerate() {
+            assert_eq!(Some(x), tree.select(i).and_then(|handle| tree.get(handle)));
+            assert_eq!(i, tree.rank(x));
+            // Odd values are missing, so they rank like the next item
+            assert_eq!(i, tree.rank(&(x - 1)));
+        }
+        assert_eq!(None, tree.select(expected.len()));
+        assert_eq!(expected.len(), tree.rank(&1000));
+
+        let mut iter = tree.iter();
+        assert_eq!(Some(&expected[10]), iter.nth(10));
+        assert_eq!(expected.len() - 11, iter.len());
+        assert_eq!(Some(&expected[11]), iter.next());
+        assert_eq!(Some(expected.last().unwrap()), iter.next_back());
+        assert_eq!(None, iter.nth(expected.len()));
+        assert_eq!(0, iter.len());
+
+        let mut range = tree.range(20..=40);
+        assert_eq!(Some(&28), range.nth(3));
+        assert_eq!(Some(&38), range.nth(2));
+        assert_eq!(None, range.nth(1));
+        assert_eq!(None, tree.range(20..=40).nth(9));
+    }
+
+    #[test]
+    fn order_statistics_in_shared_storage() {
+        let storage = storage::SharedVecStorage::with_augment(SubtreeSize);
+        let mut tree = storage.add_empty_tree();
+        let mut tree2 = storage.add_empty_tree();
+        for x in 0..10 {
+            tree.insert(x);
+        }
+        for x in [2, 5, 7] {
+            tree.move_to(tree.find(&x).unwrap(), &mut tree2).unwrap();
+        }
+        tree.validate_constraints();
+        tree2.validate_constraints();
+        assert_eq!(Some(5), tree2.select(1).and_then(|x| tree2.get_copied(x)));
+        assert_eq!(4, tree.rank(&6));
+        assert_eq!(Some(8), tree.iter_copied().nth(5));
+    }
 }
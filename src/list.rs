@@ -0,0 +1,363 @@
+//! Sequence ordered by position instead of [`Ord`]. Items are found by descending along subtree sizes,
+//! so the storage has to count them, e.g. with [`SubtreeSize`].
+
+use core::iter::Copied;
+
+use crate::key::OptionKey;
+use crate::storage::{InternalRefStorage, InternalStorage, Storage};
+use crate::{HandleError, Iter, NodeHandle, OrderStatistic, RedBlackTreeSet, COUNTED};
+
+#[cfg(feature = "alloc")]
+use crate::storage::{SharedVecStorage, VecStorage};
+#[cfg(feature = "alloc")]
+use crate::SubtreeSize;
+
+pub struct RedBlackTreeList<TStorage> {
+    // Positions need no comparator
+    tree: RedBlackTreeSet<TStorage, ()>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> RedBlackTreeList<VecStorage<T, SubtreeSize>> {
+    pub fn new() -> Self {
+        Self::with_augment(SubtreeSize)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: OrderStatistic<T>> RedBlackTreeList<VecStorage<T, A>> {
+    /// Creates an empty list whose nodes keep summaries of `augment`, which has to count items
+    pub fn with_augment(augment: A) -> Self {
+        RedBlackTreeList {
+            tree: RedBlackTreeSet {
+                nodes: VecStorage::with_augment(augment),
+                root: OptionKey::none(),
+                len: 0,
+                compare: (),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Default for RedBlackTreeList<VecStorage<T, SubtreeSize>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: OrderStatistic<T>> SharedVecStorage<T, A> {
+    pub fn add_empty_list(&self) -> RedBlackTreeList<&SharedVecStorage<T, A>> {
+        RedBlackTreeList {
+            tree: RedBlackTreeSet {
+                nodes: self,
+                root: OptionKey::none(),
+                len: 0,
+                compare: (),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: OrderStatistic<T> + Clone> RedBlackTreeList<VecStorage<T, A>> {
+    /// Splits the list before `index`. The items from `index` on are moved into a new storage, which takes O(n - index).
+    /// Their handles are invalidated: the head reports them as stale and the tail rejects all handles from before the split.
+    ///
+    /// Panics if `index > len`
+    pub fn split_at(mut self, index: usize) -> (Self, Self) {
+        let (left, right) = self.split_roots(index);
+        let mut tail = Self::with_augment(self.tree.nodes.augment().clone());
+        if let Some(right_idx) = right.get() {
+            let tail_root =
+                self.tree
+                    .nodes
+                    .move_subtree(right_idx, &mut tail.tree.nodes, OptionKey::none());
+            tail.tree.root = OptionKey::new(tail_root);
+        }
+        tail.tree.len = self.tree.len - index;
+        self.tree.root = left;
+        self.tree.len = index;
+        (self, tail)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: OrderStatistic<T>> RedBlackTreeList<&SharedVecStorage<T, A>> {
+    /// Splits the list before `index` in O(log n). Both lists stay in the storage and all handles remain valid.
+    ///
+    /// Panics if `index > len`
+    pub fn split_at(mut self, index: usize) -> (Self, Self) {
+        let (left, right) = self.split_roots(index);
+        let tail = RedBlackTreeList {
+            tree: RedBlackTreeSet {
+                nodes: self.tree.nodes,
+                root: right,
+                len: self.tree.len - index,
+                compare: (),
+            },
+        };
+        self.tree.root = left;
+        self.tree.len = index;
+        (self, tail)
+    }
+}
+
+impl<TStorage: InternalStorage> RedBlackTreeList<TStorage>
+where
+    TStorage::Augment: OrderStatistic<<TStorage as Storage>::Item>,
+{
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Inserts `value` at position `index`, shifting all items after it by one.
+    ///
+    /// Panics if `index > len`
+    pub fn insert_at(&mut self, index: usize, value: <TStorage as Storage>::Item) -> NodeHandle {
+        let len = self.len();
+        assert!(
+            index <= len,
+            "insertion index (is {index}) should be <= len (is {len})"
+        );
+        // The new node becomes the rightmost node before the item at `index`
+        let (parent, is_right) = match self.node_at(index) {
+            Some(node_idx) => match self.tree.nodes.get(node_idx).left.get() {
+                Some(left) => (self.tree.nodes.rightmost(left), true),
+                None => (node_idx, false),
+            },
+            None => match self.tree.last_node() {
+                Some(last) => (last, true),
+                None => {
+                    let node_idx = self.tree.insert_leaf(value, OptionKey::none(), false);
+                    return self.tree.handle(node_idx);
+                }
+            },
+        };
+        let node_idx = self
+            .tree
+            .insert_leaf(value, OptionKey::new(parent), is_right);
+        self.tree.handle(node_idx)
+    }
+
+    pub fn push_front(&mut self, value: <TStorage as Storage>::Item) -> NodeHandle {
+        self.insert_at(0, value)
+    }
+
+    pub fn push_back(&mut self, value: <TStorage as Storage>::Item) -> NodeHandle {
+        self.insert_at(self.len(), value)
+    }
+
+    /// Removes the item at `index`, shifting all items after it
+    pub fn remove_at(&mut self, index: usize) -> Option<<TStorage as Storage>::Item> {
+        let node_idx = self.node_at(index)?;
+        Some(self.tree.remove_node(node_idx))
+    }
+
+    pub fn pop_front(&mut self) -> Option<<TStorage as Storage>::Item> {
+        self.tree.pop_first()
+    }
+
+    pub fn pop_back(&mut self) -> Option<<TStorage as Storage>::Item> {
+        self.tree.pop_last()
+    }
+
+    pub fn get<'a>(&'a self, index: usize) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        Some(&self.tree.nodes.get(self.node_at(index)?).value)
+    }
+
+    pub fn get_copied(&self, index: usize) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        Some(self.tree.nodes.get(self.node_at(index)?).value)
+    }
+
+    /// Current position of the item inserted with `handle`, which changes as items are inserted or removed before it
+    pub fn index_of(&self, handle: NodeHandle) -> Result<usize, HandleError> {
+        let node_idx = self.tree.check_handle(handle)?;
+        Ok(self.tree.nodes.position(node_idx).expect(COUNTED))
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.iter()
+    }
+
+    pub fn iter_copied<'a>(&'a self) -> Copied<Iter<'a, TStorage>>
+    where
+        <TStorage as Storage>::Item: 'a + Copy,
+    {
+        self.tree.iter_copied()
+    }
+
+    fn node_at(&self, index: usize) -> Option<usize> {
+        self.tree
+            .nodes
+            .select(self.tree.root, index)
+            .expect(COUNTED)
+            .get()
+    }
+
+    /// Roots of the items before `index` and of the remaining ones. The tree itself is left without root.
+    #[cfg(feature = "alloc")]
+    fn split_roots(&mut self, index: usize) -> (OptionKey, OptionKey) {
+        let len = self.len();
+        assert!(
+            index <= len,
+            "split index (is {index}) should be <= len (is {len})"
+        );
+        // Nodes are visited from the root down, so the items left of the path are counted on the way
+        let mut before = 0;
+        let ((left, _), (right, _)) = self.tree.split_by(|tree, node_idx| {
+            let node = tree.nodes.get(node_idx);
+            let position = before + tree.nodes.subtree_len(node.left).expect(COUNTED);
+            let goes_left = position < index;
+            if goes_left {
+                before = position + 1;
+            }
+            goes_left
+        });
+        self.tree.root = OptionKey::none();
+        (left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_vec() {
+        let mut list = RedBlackTreeList::new();
+        let mut expected = Vec::new();
+        for x in 0..200u32 {
+            let index = (x as usize * 7) % (expected.len() + 1);
+            list.insert_at(index, x);
+            expected.insert(index, x);
+            if x % 3 == 0 {
+                let index = (x as usize * 13) % expected.len();
+                assert_eq!(Some(expected.remove(index)), list.remove_at(index));
+            }
+        }
+        list.tree.validate_constraints();
+        assert_eq!(expected, list.iter_copied().collect::<Vec<_>>());
+        for (index, x) in expected.iter().enumerate() {
+            assert_eq!(Some(x), list.get(index));
+        }
+        assert_eq!(None, list.get(expected.len()));
+        assert_eq!(None, list.remove_at(expected.len()));
+    }
+
+    #[test]
+    fn undo_stack() {
+        let mut stack = RedBlackTreeList::default();
+        stack.push_back('b');
+        let handle = stack.push_back('c');
+        stack.push_front('a');
+        assert_eq!(Ok(2), stack.index_of(handle));
+        assert_eq!(Some('a'), stack.pop_front());
+        assert_eq!(Ok(1), stack.index_of(handle));
+        assert_eq!(Some('c'), stack.pop_back());
+        assert_eq!(Err(HandleError::Stale), stack.index_of(handle));
+        assert_eq!(Some('b'), stack.get_copied(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "insertion index (is 2) should be <= len (is 1)")]
+    fn insert_out_of_bounds() {
+        let mut list = RedBlackTreeList::new();
+        list.push_back(1);
+        list.insert_at(2, 2);
+    }
+
+    #[test]
+    fn split_owned() {
+        // Scattered insertions and removals give the tree an irregular shape
+        let build = || {
+            let mut expected = Vec::new();
+            let mut list = RedBlackTreeList::new();
+            for x in 0..60u32 {
+                let index = (x as usize * 11) % (expected.len() + 1);
+                list.insert_at(index, x);
+                expected.insert(index, x);
+            }
+            for index in (0..expected.len()).rev().step_by(5) {
+                list.remove_at(index);
+                expected.remove(index);
+            }
+            (list, expected)
+        };
+
+        for index in 0..=48 {
+            let (list, expected) = build();
+            let (head, tail) = list.split_at(index);
+            head.tree.validate_constraints();
+            tail.tree.validate_constraints();
+            assert_eq!(&expected[..index], head.iter_copied().collect::<Vec<_>>());
+            assert_eq!(&expected[index..], tail.iter_copied().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn split_owned_invalidates_tail_handles() {
+        let mut list = RedBlackTreeList::new();
+        let handles = (0..10).map(|x| list.push_back(x)).collect::<Vec<_>>();
+        let (head, mut tail) = list.split_at(5);
+        for (x, handle) in handles.into_iter().enumerate() {
+            assert_eq!(Err(HandleError::ForeignTree), tail.index_of(handle));
+            if x < 5 {
+                assert_eq!(Ok(x), head.index_of(handle));
+            } else {
+                assert_eq!(Err(HandleError::Stale), head.index_of(handle));
+            }
+        }
+        let handle = tail.push_back(10);
+        assert_eq!(Ok(5), tail.index_of(handle));
+        assert_eq!(Err(HandleError::ForeignTree), head.index_of(handle));
+    }
+
+    #[test]
+    fn split_shared() {
+        let storage = SharedVecStorage::with_augment(SubtreeSize);
+        for index in 0..=40 {
+            let mut list = storage.add_empty_list();
+            let handles = (0..40).map(|x| list.push_back(x)).collect::<Vec<_>>();
+            let (mut head, tail) = list.split_at(index);
+            head.tree.validate_constraints();
+            tail.tree.validate_constraints();
+            assert_eq!(index, head.len());
+            assert_eq!(
+                (0..index).collect::<Vec<_>>(),
+                head.iter_copied().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                (index..40).collect::<Vec<_>>(),
+                tail.iter_copied().collect::<Vec<_>>()
+            );
+            for (x, handle) in handles.into_iter().enumerate() {
+                match x.checked_sub(index) {
+                    Some(tail_index) => assert_eq!(Ok(tail_index), tail.index_of(handle)),
+                    None => assert_eq!(Ok(x), head.index_of(handle)),
+                }
+            }
+
+            // Joined trees keep working as usual
+            head.insert_at(index / 2, 100);
+            assert_eq!(Some(100), head.get_copied(index / 2));
+            head.tree.validate_constraints();
+        }
+    }
+}
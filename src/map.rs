@@ -0,0 +1,578 @@
+//! A map on top of the same storage as [`RedBlackTreeSet`]. Items are stored as `(K, V)` and ordered by `K` only.
+//! Custom comparators therefore implement [`Compare<K>`] instead of comparing whole items.
+
+use core::{
+    cmp::Ordering,
+    iter::{Copied, FusedIterator},
+    mem,
+};
+
+use crate::key::OptionKey;
+use crate::storage::{InternalRefStorage, InternalStorage};
+use crate::{Compare, OrdCompare, RedBlackTreeSet};
+
+#[cfg(feature = "alloc")]
+use crate::storage::{SharedVecStorage, VecStorage};
+
+pub struct RedBlackTreeMap<TStorage, C = OrdCompare> {
+    tree: RedBlackTreeSet<TStorage, C>,
+}
+
+#[cfg(feature = "alloc")]
+impl<K: Ord, V> RedBlackTreeMap<VecStorage<(K, V)>> {
+    pub fn new() -> Self {
+        Self::with_comparator(OrdCompare)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V, C: Compare<K>> RedBlackTreeMap<VecStorage<(K, V)>, C> {
+    pub fn with_comparator(compare: C) -> Self {
+        RedBlackTreeMap {
+            tree: RedBlackTreeSet {
+                nodes: VecStorage::new(),
+                root: OptionKey::none(),
+                len: 0,
+                compare,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V, C: Compare<K> + Default> Default for RedBlackTreeMap<VecStorage<(K, V)>, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V> SharedVecStorage<(K, V)> {
+    pub fn add_empty_map(&self) -> RedBlackTreeMap<&SharedVecStorage<(K, V)>> {
+        self.add_empty_map_with_comparator(OrdCompare)
+    }
+
+    pub fn add_empty_map_with_comparator<C>(
+        &self,
+        compare: C,
+    ) -> RedBlackTreeMap<&SharedVecStorage<(K, V)>, C> {
+        RedBlackTreeMap {
+            tree: RedBlackTreeSet {
+                nodes: self,
+                root: OptionKey::none(),
+                len: 0,
+                compare,
+            },
+        }
+    }
+}
+
+impl<K, V, TStorage: InternalStorage<Item = (K, V)>, C: Compare<K>> RedBlackTreeMap<TStorage, C> {
+    /// Inserts `value` for `key` and returns the replaced value. The key of an existing entry is kept.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.locate(&key) {
+            Ok(node_idx) => Some(mem::replace(
+                &mut self.tree.nodes.get_mut(node_idx).value.1,
+                value,
+            )),
+            Err((parent, is_right)) => {
+                self.tree.insert_leaf((key, value), parent, is_right);
+                None
+            }
+        }
+    }
+
+    pub fn get<'a, Q: ?Sized>(&'a self, key: &Q) -> Option<&'a V>
+    where
+        C: Compare<K, Q>,
+        TStorage: InternalRefStorage,
+        K: 'a,
+        V: 'a,
+    {
+        let node_idx = self.locate(key).ok()?;
+        Some(&self.tree.nodes.get(node_idx).value.1)
+    }
+
+    pub fn get_mut<'a, Q: ?Sized>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    where
+        C: Compare<K, Q>,
+        TStorage: InternalRefStorage,
+        K: 'a,
+        V: 'a,
+    {
+        let node_idx = self.locate(key).ok()?;
+        Some(&mut self.tree.nodes.get_mut(node_idx).value.1)
+    }
+
+    pub fn get_copied<Q: ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        C: Compare<K, Q>,
+        V: Copy,
+    {
+        let node_idx = self.locate(key).ok()?;
+        Some(self.tree.nodes.get(node_idx).value.1)
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    where
+        C: Compare<K, Q>,
+    {
+        self.locate(key).is_ok()
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        C: Compare<K, Q>,
+    {
+        let node_idx = self.locate(key).ok()?;
+        Some(self.tree.remove_node(node_idx).1)
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, TStorage, C> {
+        match self.locate(&key) {
+            Ok(node_idx) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                node_idx,
+            }),
+            Err((parent, is_right)) => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                parent,
+                is_right,
+            }),
+        }
+    }
+
+    fn locate<Q: ?Sized>(&self, key: &Q) -> Result<usize, (OptionKey, bool)>
+    where
+        C: Compare<K, Q>,
+    {
+        self.tree
+            .locate_by(|node_idx| self.compare_node_key(node_idx, key))
+    }
+
+    fn compare_node_key<Q: ?Sized>(&self, node_idx: usize, key: &Q) -> Ordering
+    where
+        C: Compare<K, Q>,
+    {
+        let nodes = &self.tree.nodes;
+        nodes.callback(|| self.tree.compare.compare(&nodes.get(node_idx).value.0, key))
+    }
+}
+
+impl<K, V, TStorage: InternalStorage<Item = (K, V)>, C> RedBlackTreeMap<TStorage, C> {
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        K: 'a,
+        V: 'a,
+    {
+        Iter(self.tree.iter())
+    }
+
+    pub fn keys<'a>(&'a self) -> Keys<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        K: 'a,
+        V: 'a,
+    {
+        Keys(self.tree.iter())
+    }
+
+    pub fn values<'a>(&'a self) -> Values<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        K: 'a,
+        V: 'a,
+    {
+        Values(self.tree.iter())
+    }
+
+    pub fn iter_copied<'a>(&'a self) -> Copied<crate::Iter<'a, TStorage>>
+    where
+        K: 'a + Copy,
+        V: 'a + Copy,
+    {
+        self.tree.iter_copied()
+    }
+
+    pub fn keys_copied<'a>(&'a self) -> Copied<Keys<'a, TStorage>>
+    where
+        K: 'a + Copy,
+        V: 'a,
+    {
+        // Safety: Copied doesn't allow extraction of inner iterator
+        unsafe { Keys(self.tree.create_iterator()).copied() }
+    }
+
+    pub fn values_copied<'a>(&'a self) -> Copied<Values<'a, TStorage>>
+    where
+        K: 'a,
+        V: 'a + Copy,
+    {
+        // Safety: Copied doesn't allow extraction of inner iterator
+        unsafe { Values(self.tree.create_iterator()).copied() }
+    }
+}
+
+pub struct Iter<'a, TStorage>(crate::Iter<'a, TStorage>);
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> Iterator
+    for Iter<'a, TStorage>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> DoubleEndedIterator
+    for Iter<'a, TStorage>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(key, value)| (key, value))
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> FusedIterator
+    for Iter<'a, TStorage>
+{
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> ExactSizeIterator
+    for Iter<'a, TStorage>
+{
+}
+
+pub struct Keys<'a, TStorage>(crate::Iter<'a, TStorage>);
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> Iterator
+    for Keys<'a, TStorage>
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> DoubleEndedIterator
+    for Keys<'a, TStorage>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> FusedIterator
+    for Keys<'a, TStorage>
+{
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> ExactSizeIterator
+    for Keys<'a, TStorage>
+{
+}
+
+pub struct Values<'a, TStorage>(crate::Iter<'a, TStorage>);
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> Iterator
+    for Values<'a, TStorage>
+{
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> DoubleEndedIterator
+    for Values<'a, TStorage>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> FusedIterator
+    for Values<'a, TStorage>
+{
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: 'a + InternalStorage<Item = (K, V)>> ExactSizeIterator
+    for Values<'a, TStorage>
+{
+}
+
+pub enum Entry<'a, K, TStorage, C = OrdCompare> {
+    Vacant(VacantEntry<'a, K, TStorage, C>),
+    Occupied(OccupiedEntry<'a, TStorage, C>),
+}
+
+pub struct VacantEntry<'a, K, TStorage, C = OrdCompare> {
+    map: &'a mut RedBlackTreeMap<TStorage, C>,
+    key: K,
+    parent: OptionKey,
+    is_right: bool,
+}
+
+pub struct OccupiedEntry<'a, TStorage, C = OrdCompare> {
+    map: &'a mut RedBlackTreeMap<TStorage, C>,
+    node_idx: usize,
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: InternalRefStorage<Item = (K, V)>, C> Entry<'a, K, TStorage, C> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: InternalStorage<Item = (K, V)>, C>
+    VacantEntry<'a, K, TStorage, C>
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V
+    where
+        TStorage: InternalRefStorage,
+    {
+        self.insert_entry(value).into_mut()
+    }
+
+    /// Like [`VacantEntry::insert`], but returns the entry instead of a reference, so it works with shared storages as well
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, TStorage, C> {
+        let node_idx = self
+            .map
+            .tree
+            .insert_leaf((self.key, value), self.parent, self.is_right);
+        OccupiedEntry {
+            map: self.map,
+            node_idx,
+        }
+    }
+}
+
+impl<'a, K: 'a, V: 'a, TStorage: InternalStorage<Item = (K, V)>, C> OccupiedEntry<'a, TStorage, C> {
+    pub fn key(&self) -> &K
+    where
+        TStorage: InternalRefStorage,
+    {
+        &self.map.tree.nodes.get(self.node_idx).value.0
+    }
+
+    pub fn get(&self) -> &V
+    where
+        TStorage: InternalRefStorage,
+    {
+        &self.map.tree.nodes.get(self.node_idx).value.1
+    }
+
+    pub fn get_copied(&self) -> V
+    where
+        V: Copy,
+    {
+        self.map.tree.nodes.get(self.node_idx).value.1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V
+    where
+        TStorage: InternalRefStorage,
+    {
+        &mut self.map.tree.nodes.get_mut(self.node_idx).value.1
+    }
+
+    pub fn into_mut(self) -> &'a mut V
+    where
+        TStorage: InternalRefStorage,
+    {
+        &mut self.map.tree.nodes.get_mut(self.node_idx).value.1
+    }
+
+    /// Replaces the value and returns the previous one
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(
+            &mut self.map.tree.nodes.get_mut(self.node_idx).value.1,
+            value,
+        )
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.tree.remove_node(self.node_idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_replaces_value_only() {
+        let mut map = RedBlackTreeMap::new();
+        assert_eq!(None, map.insert(2, "two"));
+        assert_eq!(None, map.insert(1, "one"));
+        assert_eq!(Some("two"), map.insert(2, "zwei"));
+        map.tree.validate_constraints();
+
+        assert_eq!(Some(&"zwei"), map.get(&2));
+        assert_eq!(None, map.get(&3));
+        *map.get_mut(&1).unwrap() = "eins";
+        assert_eq!(vec![&2, &1], map.keys().rev().collect::<Vec<_>>());
+        assert_eq!(vec![&"eins", &"zwei"], map.values().collect::<Vec<_>>());
+        assert_eq!(
+            vec![(&1, &"eins"), (&2, &"zwei")],
+            map.iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(2, map.len());
+        assert_eq!(2, map.values().len());
+        assert_eq!(Some("eins"), map.remove(&1));
+        assert_eq!(None, map.remove(&1));
+        assert_eq!(vec![(2, "zwei")], map.iter_copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn entry() {
+        let mut map = RedBlackTreeMap::new();
+        for word in ["b", "a", "b", "c", "b"] {
+            *map.entry(word).or_insert(0) += 1;
+        }
+        map.entry("a").and_modify(|x| *x += 10).or_default();
+        map.entry("d").and_modify(|x| *x += 10).or_default();
+        assert_eq!(
+            vec![("a", 11), ("b", 3), ("c", 1), ("d", 0)],
+            map.iter_copied().collect::<Vec<_>>()
+        );
+
+        let Entry::Occupied(entry) = map.entry("b") else {
+            panic!("Expected occupied entry");
+        };
+        assert_eq!(("b", 3), entry.remove_entry());
+        let Entry::Vacant(entry) = map.entry("b") else {
+            panic!("Expected vacant entry");
+        };
+        assert_eq!("b", entry.into_key());
+    }
+
+    #[test]
+    fn shared_storage() {
+        let storage = SharedVecStorage::new();
+        let mut map = storage.add_empty_map();
+        let mut map2 = storage.add_empty_map();
+        map.insert(1, 10);
+        map2.insert(1, 20);
+        map2.insert(0, 0);
+
+        assert_eq!(Some(10), map.get_copied(&1));
+        let Entry::Occupied(mut entry) = map2.entry(1) else {
+            panic!("Expected occupied entry");
+        };
+        assert_eq!(20, entry.insert(21));
+        assert_eq!(vec![(1, 10)], map.iter_copied().collect::<Vec<_>>());
+        assert_eq!(
+            vec![(0, 0), (1, 21)],
+            map2.iter_copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn shared_entry_and_copied_iterators() {
+        let storage = SharedVecStorage::new();
+        let mut counts = storage.add_empty_map();
+        for word in ["b", "a", "b", "c", "b"] {
+            match counts.entry(word) {
+                Entry::Vacant(entry) => {
+                    assert_eq!(1, entry.insert_entry(1).get_copied());
+                }
+                Entry::Occupied(mut entry) => {
+                    let count = entry.get_copied();
+                    entry.insert(count + 1);
+                }
+            }
+        }
+        assert_eq!(
+            vec!["a", "b", "c"],
+            counts.keys_copied().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![1, 3, 1], counts.values_copied().collect::<Vec<_>>());
+
+        // Keys can be copied even if values can't
+        let storage = SharedVecStorage::new();
+        let mut names = storage.add_empty_map();
+        names.insert(2, String::from("two"));
+        names.insert(1, String::from("one"));
+        assert_eq!(vec![1, 2], names.keys_copied().collect::<Vec<_>>());
+        assert_eq!(2, names.keys_copied().len());
+    }
+
+    #[test]
+    fn get_by_borrowed_key() {
+        let mut map = RedBlackTreeMap::new();
+        map.insert(String::from("a"), 1);
+        map.insert(String::from("b"), 2);
+        assert_eq!(Some(&1), map.get("a"));
+        assert_eq!(Some(2), map.get_copied("b"));
+        *map.get_mut("b").unwrap() += 1;
+        assert!(map.contains_key("b"));
+        assert_eq!(Some(3), map.remove("b"));
+        assert!(!map.contains_key("b"));
+    }
+}
@@ -0,0 +1,365 @@
+//! Like [`RedBlackTreeSet`], but equal items are kept side by side in insertion order
+
+use core::{
+    cmp::Ordering,
+    iter::Copied,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::storage::{InternalRefStorage, InternalStorage, Storage};
+use crate::{
+    Compare, HandleError, Iter, NodeHandle, OrdCompare, OrderStatistic, Range, RedBlackTreeSet,
+};
+
+#[cfg(feature = "alloc")]
+use crate::storage::{SharedVecStorage, VecStorage};
+#[cfg(feature = "alloc")]
+use crate::Augment;
+
+pub struct RedBlackTreeMultiSet<TStorage, C = OrdCompare> {
+    tree: RedBlackTreeSet<TStorage, C>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Ord> RedBlackTreeMultiSet<VecStorage<T>> {
+    pub fn new() -> Self {
+        Self::with_comparator(OrdCompare)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, C: Compare<T>> RedBlackTreeMultiSet<VecStorage<T>, C> {
+    pub fn with_comparator(compare: C) -> Self {
+        RedBlackTreeMultiSet {
+            tree: RedBlackTreeSet::with_comparator(compare),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, C: Compare<T> + Default> Default for RedBlackTreeMultiSet<VecStorage<T>, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, A: Augment<T>> SharedVecStorage<T, A> {
+    pub fn add_empty_multiset(&self) -> RedBlackTreeMultiSet<&SharedVecStorage<T, A>> {
+        self.add_empty_multiset_with_comparator(OrdCompare)
+    }
+
+    pub fn add_empty_multiset_with_comparator<C>(
+        &self,
+        compare: C,
+    ) -> RedBlackTreeMultiSet<&SharedVecStorage<T, A>, C> {
+        RedBlackTreeMultiSet {
+            tree: self.add_empty_tree_with_comparator(compare),
+        }
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeMultiSet<TStorage, C>
+{
+    /// Inserts `value` behind all equal items. A node is created in any case.
+    pub fn insert(&mut self, value: <TStorage as Storage>::Item) -> NodeHandle {
+        // Treating equal nodes as less descends right of them
+        let position = self.tree.locate_by(|node_idx| {
+            self.tree
+                .compare_node_value(node_idx, &value)
+                .then(Ordering::Less)
+        });
+        let Err((parent, is_right)) = position else {
+            unreachable!("No node compares equal")
+        };
+        let node_idx = self.tree.insert_leaf(value, parent, is_right);
+        self.tree.handle(node_idx)
+    }
+
+    /// First inserted of the items equal to `value`
+    pub fn find<Q: ?Sized>(&self, value: &Q) -> Option<NodeHandle>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let node_idx = self.tree.lower_bound_node(Bound::Included(value)).get()?;
+        self.tree
+            .compare_node_value(node_idx, value)
+            .is_eq()
+            .then(|| self.tree.handle(node_idx))
+    }
+
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.find(value).is_some()
+    }
+
+    /// Number of items equal to `value`
+    pub fn count<Q: ?Sized>(&self, value: &Q) -> usize
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let mut count = 0;
+        let mut current = self.tree.lower_bound_node(Bound::Included(value));
+        while let Some(node_idx) = current.get() {
+            if !self.tree.compare_node_value(node_idx, value).is_eq() {
+                break;
+            }
+            count += 1;
+            current = self.tree.nodes.successor(node_idx);
+        }
+        count
+    }
+
+    /// Removes the first inserted of the items equal to `value`
+    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> Option<<TStorage as Storage>::Item>
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        let handle = self.find(value)?;
+        Some(self.tree.remove_node(handle.index()))
+    }
+
+    pub fn range<'a, Q: ?Sized>(&'a self, range: impl RangeBounds<Q>) -> Range<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+        C: Compare<<TStorage as Storage>::Item, Q> + Compare<Q>,
+    {
+        self.tree.range(range)
+    }
+
+    pub fn range_copied<'a, Q: ?Sized>(
+        &'a self,
+        range: impl RangeBounds<Q>,
+    ) -> Copied<Range<'a, TStorage>>
+    where
+        <TStorage as Storage>::Item: 'a + Copy,
+        C: Compare<<TStorage as Storage>::Item, Q> + Compare<Q>,
+    {
+        self.tree.range_copied(range)
+    }
+}
+
+impl<TStorage: InternalStorage, C: Compare<<TStorage as Storage>::Item>>
+    RedBlackTreeMultiSet<TStorage, C>
+where
+    TStorage::Augment: OrderStatistic<<TStorage as Storage>::Item>,
+{
+    /// Item at the 0-based position `index`, where equal items are ordered by insertion
+    pub fn select(&self, index: usize) -> Option<NodeHandle> {
+        self.tree.select(index)
+    }
+
+    /// Number of items below `value`, which is the position of the first equal item
+    pub fn rank<Q: ?Sized>(&self, value: &Q) -> usize
+    where
+        C: Compare<<TStorage as Storage>::Item, Q>,
+    {
+        self.tree.rank(value)
+    }
+}
+
+impl<TStorage: InternalStorage, C> RedBlackTreeMultiSet<TStorage, C> {
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn remove_at(
+        &mut self,
+        handle: NodeHandle,
+    ) -> Result<<TStorage as Storage>::Item, HandleError> {
+        self.tree.remove_at(handle)
+    }
+
+    pub fn get<'a>(&'a self, handle: NodeHandle) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.get(handle)
+    }
+
+    pub fn get_copied(&self, handle: NodeHandle) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.tree.get_copied(handle)
+    }
+
+    pub fn contains_handle(&self, handle: NodeHandle) -> bool {
+        self.tree.contains_handle(handle)
+    }
+
+    /// First inserted of the smallest items
+    pub fn first<'a>(&'a self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.first()
+    }
+
+    pub fn first_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.tree.first_copied()
+    }
+
+    pub fn first_handle(&self) -> Option<NodeHandle> {
+        self.tree.first_handle()
+    }
+
+    /// Removes the first inserted of the smallest items, so equal priorities are processed in FIFO order
+    pub fn pop_first(&mut self) -> Option<<TStorage as Storage>::Item> {
+        self.tree.pop_first()
+    }
+
+    /// Last inserted of the largest items
+    pub fn last<'a>(&'a self) -> Option<&'a <TStorage as Storage>::Item>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.last()
+    }
+
+    pub fn last_copied(&self) -> Option<<TStorage as Storage>::Item>
+    where
+        <TStorage as Storage>::Item: Copy,
+    {
+        self.tree.last_copied()
+    }
+
+    pub fn last_handle(&self) -> Option<NodeHandle> {
+        self.tree.last_handle()
+    }
+
+    pub fn pop_last(&mut self) -> Option<<TStorage as Storage>::Item> {
+        self.tree.pop_last()
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, TStorage>
+    where
+        TStorage: InternalRefStorage,
+        <TStorage as Storage>::Item: 'a,
+    {
+        self.tree.iter()
+    }
+
+    pub fn iter_copied<'a>(&'a self) -> Copied<Iter<'a, TStorage>>
+    where
+        <TStorage as Storage>::Item: 'a + Copy,
+    {
+        self.tree.iter_copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ordered by the first field only
+    #[derive(Debug, Clone, Copy)]
+    struct Tagged(u8, char);
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+    impl Eq for Tagged {}
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for Tagged {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[test]
+    fn keeps_insertion_order_among_equals() {
+        let mut set = RedBlackTreeMultiSet::new();
+        for (i, tag) in "abcdefghij".chars().enumerate() {
+            set.insert(Tagged(i as u8 % 3, tag));
+        }
+        set.tree.validate_constraints();
+        assert_eq!(10, set.len());
+        assert_eq!(4, set.count(&Tagged(0, ' ')));
+        assert_eq!(0, set.count(&Tagged(5, ' ')));
+        assert_eq!("adgjbehcfi", set.iter().map(|x| x.1).collect::<String>());
+
+        assert_eq!(Some('a'), set.remove(&Tagged(0, ' ')).map(|x| x.1));
+        assert_eq!(
+            Some('d'),
+            set.find(&Tagged(0, ' '))
+                .and_then(|x| set.get(x))
+                .map(|x| x.1)
+        );
+        assert_eq!(
+            "behcfi",
+            set.range(Tagged(1, ' ')..).map(|x| x.1).collect::<String>()
+        );
+        set.tree.validate_constraints();
+    }
+
+    #[test]
+    fn shared_storage() {
+        let storage = SharedVecStorage::new();
+        let mut set = storage.add_empty_multiset();
+        let mut set2 = storage.add_empty_multiset();
+        let handle = set.insert(1);
+        set.insert(1);
+        set2.insert(1);
+
+        assert_eq!(vec![1, 1], set.iter_copied().collect::<Vec<_>>());
+        assert_eq!(Err(HandleError::ForeignTree), set2.remove_at(handle));
+        assert_eq!(Ok(1), set.remove_at(handle));
+        assert_eq!(1, set.count(&1));
+        assert!(!set.contains(&2));
+    }
+
+    #[test]
+    fn work_queue() {
+        let mut queue = RedBlackTreeMultiSet::new();
+        for (priority, tag) in [(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd')] {
+            queue.insert(Tagged(priority, tag));
+        }
+        assert_eq!(Some('b'), queue.first().map(|x| x.1));
+        assert_eq!(Some('c'), queue.last().map(|x| x.1));
+        assert_eq!(Some('c'), queue.pop_last().map(|x| x.1));
+        assert_eq!(
+            "bda",
+            core::iter::from_fn(|| queue.pop_first())
+                .map(|x| x.1)
+                .collect::<String>()
+        );
+        assert_eq!(None, queue.first_handle());
+        queue.tree.validate_constraints();
+    }
+
+    #[test]
+    fn percentiles() {
+        let storage = SharedVecStorage::with_augment(crate::SubtreeSize);
+        let mut set = storage.add_empty_multiset();
+        for x in [5, 1, 5, 3, 5, 9] {
+            set.insert(x);
+        }
+        assert_eq!(2, set.rank(&5));
+        assert_eq!(5, set.rank(&6));
+        assert_eq!(Some(5), set.select(4).and_then(|x| set.get_copied(x)));
+        assert_eq!(Some(9), set.select(5).and_then(|x| set.get_copied(x)));
+        assert_eq!(None, set.select(6));
+        assert_eq!(Some(5), set.iter_copied().nth(3));
+    }
+}
@@ -8,30 +8,32 @@ pub(crate) enum Color {
 
 // Type is public but only referenced in trait, which is sealed
 #[derive(Debug, PartialEq, Clone)]
-pub struct Node<T> {
+pub struct Node<T, S = ()> {
     pub(crate) value: T,
+    /// Summary of the subtree rooted here, see [`crate::Augment`]
+    pub(crate) summary: S,
     pub(crate) color: Color,
     pub(crate) parent: OptionKey,
     pub(crate) left: OptionKey,
     pub(crate) right: OptionKey,
 }
 
-impl<T> Node<T> {
-    #[inline(always)]
-    pub fn is_right(&self, key: usize) -> bool {
-        debug_assert!(key != usize::MAX);
-        self.right == key
-    }
-}
-
-impl<T> From<T> for Node<T> {
-    fn from(value: T) -> Self {
+impl<T, S> Node<T, S> {
+    /// Detached red node
+    pub(crate) fn new(value: T, summary: S) -> Self {
         Self {
             value,
+            summary,
             color: Color::Red,
             parent: Default::default(),
             left: Default::default(),
             right: Default::default(),
         }
     }
+
+    #[inline(always)]
+    pub fn is_right(&self, key: usize) -> bool {
+        debug_assert!(key != usize::MAX);
+        self.right == key
+    }
 }
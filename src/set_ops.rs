@@ -0,0 +1,469 @@
+//! Set algebra on two trees, which may use different storages. All operations walk both trees in order
+//! side by side, so they take O(n + m) and allocate nothing. Items are compared with the comparator of `self`,
+//! so `other` has to be ordered the same way.
+
+use core::{
+    cmp::Ordering,
+    iter::{Copied, FusedIterator},
+};
+
+use crate::storage::{InternalRefStorage, InternalStorage};
+use crate::{Compare, Iter, RedBlackTreeSet};
+
+/// Walks two trees in order and pairs up equal items. Both sides are peeked by node index, as references into a
+/// shared storage mustn't outlive a call: other trees of the storage might reallocate it in between.
+struct MergeIter<'a, S1, S2, C> {
+    left: Iter<'a, S1>,
+    right: Iter<'a, S2>,
+    compare: &'a C,
+}
+
+impl<'a, T: 'a, S1, S2, C> MergeIter<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+    /// Next item of either tree, or of both if they are equal
+    fn next_pair(&mut self) -> (Option<&'a T>, Option<&'a T>) {
+        let ordering = match (self.left.peek_node(), self.right.peek_node()) {
+            (Some(left), Some(right)) => {
+                let (left_nodes, right_nodes) = (self.left.nodes(), self.right.nodes());
+                left_nodes.callback(|| {
+                    right_nodes.callback(|| {
+                        self.compare
+                            .compare(&left_nodes.get(left).value, &right_nodes.get(right).value)
+                    })
+                })
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return (None, None),
+        };
+        let (left, right) = match ordering {
+            Ordering::Less => (self.left.next_node(), None),
+            Ordering::Greater => (None, self.right.next_node()),
+            Ordering::Equal => (self.left.next_node(), self.right.next_node()),
+        };
+        (
+            left.map(|node_idx| &self.left.nodes().get(node_idx).value),
+            right.map(|node_idx| &self.right.nodes().get(node_idx).value),
+        )
+    }
+
+    fn lens(&self) -> (usize, usize) {
+        (self.left.len(), self.right.len())
+    }
+}
+
+/// Items of either tree in order, see [`RedBlackTreeSet::union`]
+pub struct Union<'a, S1, S2, C>(MergeIter<'a, S1, S2, C>);
+
+/// Items of both trees in order, see [`RedBlackTreeSet::intersection`]
+pub struct Intersection<'a, S1, S2, C>(MergeIter<'a, S1, S2, C>);
+
+/// Items of the first tree missing in the second, see [`RedBlackTreeSet::difference`]
+pub struct Difference<'a, S1, S2, C>(MergeIter<'a, S1, S2, C>);
+
+/// Items of exactly one tree in order, see [`RedBlackTreeSet::symmetric_difference`]
+pub struct SymmetricDifference<'a, S1, S2, C>(MergeIter<'a, S1, S2, C>);
+
+impl<'a, T: 'a, S1, S2, C> Iterator for Union<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+    type Item = &'a T;
+
+    /// Equal items are taken from the first tree
+    fn next(&mut self) -> Option<Self::Item> {
+        let (left, right) = self.0.next_pair();
+        left.or(right)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (left, right) = self.0.lens();
+        (left.max(right), Some(left + right))
+    }
+}
+
+impl<'a, T: 'a, S1, S2, C> Iterator for Intersection<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+    type Item = &'a T;
+
+    /// Equal items are taken from the first tree
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next_pair() {
+                (Some(left), Some(_)) => return Some(left),
+                (None, None) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (left, right) = self.0.lens();
+        (0, Some(left.min(right)))
+    }
+}
+
+impl<'a, T: 'a, S1, S2, C> Iterator for Difference<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next_pair() {
+                (Some(left), None) => return Some(left),
+                (None, None) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (left, right) = self.0.lens();
+        (left.saturating_sub(right), Some(left))
+    }
+}
+
+impl<'a, T: 'a, S1, S2, C> Iterator for SymmetricDifference<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next_pair() {
+                (Some(_), Some(_)) => {}
+                (left, right) => return left.or(right),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (left, right) = self.0.lens();
+        (0, Some(left + right))
+    }
+}
+
+// Both inner iterators are fused, so `next_pair` keeps returning nothing once both are exhausted
+impl<'a, T: 'a, S1, S2, C> FusedIterator for Union<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+}
+
+impl<'a, T: 'a, S1, S2, C> FusedIterator for Intersection<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+}
+
+impl<'a, T: 'a, S1, S2, C> FusedIterator for Difference<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+}
+
+impl<'a, T: 'a, S1, S2, C> FusedIterator for SymmetricDifference<'a, S1, S2, C>
+where
+    S1: 'a + InternalStorage<Item = T>,
+    S2: 'a + InternalStorage<Item = T>,
+    C: Compare<T>,
+{
+}
+
+impl<T, S1: InternalStorage<Item = T>, C: Compare<T>> RedBlackTreeSet<S1, C> {
+    /// Safety: References musten't be accessible in safe code, if one of the storages doesn't implement InternalRefStorage
+    unsafe fn merge<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> MergeIter<'a, S1, S2, C>
+    where
+        T: 'a,
+    {
+        MergeIter {
+            left: self.create_iterator(),
+            right: other.create_iterator(),
+            compare: &self.compare,
+        }
+    }
+
+    /// Items which are in `self`, `other` or both, in order
+    pub fn union<'a, S2: InternalRefStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Union<'a, S1, S2, C>
+    where
+        S1: InternalRefStorage,
+        T: 'a,
+    {
+        Union(unsafe { self.merge(other) })
+    }
+
+    /// Like [`RedBlackTreeSet::union`], but for any storage, e.g. to combine an owned with a shared tree
+    pub fn union_copied<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Copied<Union<'a, S1, S2, C>>
+    where
+        T: 'a + Copy,
+    {
+        // Safety: Copied doesn't allow extraction of inner iterator and items are copied as soon as they are returned
+        unsafe { Union(self.merge(other)).copied() }
+    }
+
+    /// Items which are in both `self` and `other`, in order
+    pub fn intersection<'a, S2: InternalRefStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Intersection<'a, S1, S2, C>
+    where
+        S1: InternalRefStorage,
+        T: 'a,
+    {
+        Intersection(unsafe { self.merge(other) })
+    }
+
+    pub fn intersection_copied<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Copied<Intersection<'a, S1, S2, C>>
+    where
+        T: 'a + Copy,
+    {
+        unsafe { Intersection(self.merge(other)).copied() }
+    }
+
+    /// Items which are in `self`, but not in `other`, in order
+    pub fn difference<'a, S2: InternalRefStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Difference<'a, S1, S2, C>
+    where
+        S1: InternalRefStorage,
+        T: 'a,
+    {
+        Difference(unsafe { self.merge(other) })
+    }
+
+    pub fn difference_copied<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Copied<Difference<'a, S1, S2, C>>
+    where
+        T: 'a + Copy,
+    {
+        unsafe { Difference(self.merge(other)).copied() }
+    }
+
+    /// Items which are in exactly one of `self` and `other`, in order
+    pub fn symmetric_difference<'a, S2: InternalRefStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> SymmetricDifference<'a, S1, S2, C>
+    where
+        S1: InternalRefStorage,
+        T: 'a,
+    {
+        SymmetricDifference(unsafe { self.merge(other) })
+    }
+
+    pub fn symmetric_difference_copied<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> Copied<SymmetricDifference<'a, S1, S2, C>>
+    where
+        T: 'a + Copy,
+    {
+        unsafe { SymmetricDifference(self.merge(other)).copied() }
+    }
+
+    /// Whether all items of `self` are in `other`. Works with any storage, as no items are returned.
+    pub fn is_subset<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> bool
+    where
+        T: 'a,
+    {
+        if self.len > other.len {
+            return false;
+        }
+        // Safety: The items are only compared
+        unsafe { Difference(self.merge(other)).next().is_none() }
+    }
+
+    /// Whether all items of `other` are in `self`
+    pub fn is_superset<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> bool
+    where
+        T: 'a,
+    {
+        if self.len < other.len {
+            return false;
+        }
+        // Items of `other` missing in `self` are the second half of the symmetric difference
+        unsafe {
+            let mut merge = self.merge(other);
+            loop {
+                match merge.next_pair() {
+                    (None, Some(_)) => return false,
+                    (None, None) => return true,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Whether `self` and `other` have no items in common
+    pub fn is_disjoint<'a, S2: InternalStorage<Item = T>>(
+        &'a self,
+        other: &'a RedBlackTreeSet<S2, C>,
+    ) -> bool
+    where
+        T: 'a,
+    {
+        unsafe { Intersection(self.merge(other)).next().is_none() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SharedVecStorage;
+
+    #[test]
+    fn permission_sets() {
+        let mut granted = RedBlackTreeSet::new();
+        let mut required = RedBlackTreeSet::new();
+        for x in ["read", "write", "admin"] {
+            granted.insert(x);
+        }
+        for x in ["read", "delete"] {
+            required.insert(x);
+        }
+
+        assert_eq!(
+            vec![&"admin", &"delete", &"read", &"write"],
+            granted.union(&required).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&"read"],
+            granted.intersection(&required).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&"delete"],
+            required.difference(&granted).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&"admin", &"delete", &"write"],
+            granted.symmetric_difference(&required).collect::<Vec<_>>()
+        );
+        assert!(!required.is_subset(&granted));
+        assert!(!granted.is_superset(&required));
+        assert!(!granted.is_disjoint(&required));
+
+        required.remove(&"delete");
+        assert!(required.is_subset(&granted));
+        assert!(granted.is_superset(&required));
+        assert!(!required.is_superset(&granted));
+        required.remove(&"read");
+        assert!(granted.is_disjoint(&required));
+        assert!(required.is_subset(&granted));
+    }
+
+    #[test]
+    fn sibling_tree_grows_storage_between_items() {
+        let storage = SharedVecStorage::new();
+        let mut shared = storage.add_empty_tree();
+        let mut sibling = storage.add_empty_tree();
+        let mut owned = RedBlackTreeSet::new();
+        for x in 0..20 {
+            shared.insert(100 + 2 * x);
+            owned.insert(1 + 2 * x);
+        }
+
+        // The shared side is peeked while the sibling reallocates the storage
+        let mut union = shared.union_copied(&owned);
+        assert_eq!(Some(1), union.next());
+        for x in 0..10_000 {
+            sibling.insert(x);
+        }
+        let expected = (1..40)
+            .step_by(2)
+            .chain((100..140).step_by(2))
+            .collect::<Vec<_>>();
+        assert_eq!(expected[1..], union.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn owned_and_shared() {
+        let storage = SharedVecStorage::new();
+        let mut shared = storage.add_empty_tree();
+        let mut owned = RedBlackTreeSet::new();
+        for x in (0..30).step_by(2) {
+            shared.insert(x);
+        }
+        for x in (0..30).step_by(3) {
+            owned.insert(x);
+        }
+
+        let multiples = |step: usize| (0..30).filter(move |x| x % step == 0);
+        assert_eq!(
+            multiples(6).collect::<Vec<_>>(),
+            owned.intersection_copied(&shared).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (0..30)
+                .filter(|x| x % 2 == 0 || x % 3 == 0)
+                .collect::<Vec<_>>(),
+            shared.union_copied(&owned).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            multiples(2).filter(|x| x % 3 != 0).collect::<Vec<_>>(),
+            shared.difference_copied(&owned).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (0..30)
+                .filter(|x| (x % 2 == 0) != (x % 3 == 0))
+                .collect::<Vec<_>>(),
+            owned
+                .symmetric_difference_copied(&shared)
+                .collect::<Vec<_>>()
+        );
+
+        let mut sixes = storage.add_empty_tree();
+        for x in multiples(6) {
+            sixes.insert(x);
+        }
+        assert!(sixes.is_subset(&shared));
+        assert!(sixes.is_subset(&owned));
+        assert!(owned.is_superset(&sixes));
+        assert!(!shared.is_subset(&owned));
+        assert!(!sixes.is_disjoint(&owned));
+        assert!(RedBlackTreeSet::<_>::new().is_disjoint(&shared));
+    }
+}
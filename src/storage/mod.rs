@@ -1,4 +1,4 @@
-use crate::node::Node;
+use crate::{augment::Augment, key::OptionKey, node::Node};
 
 #[cfg(feature = "alloc")]
 mod owned;
@@ -10,7 +10,7 @@ mod shared;
 pub use owned::VecStorage;
 
 #[cfg(feature = "alloc")]
-pub use shared::SharedVecStorage;
+pub use shared::{MoveError, SharedVecStorage};
 
 // pub struct OwnedVecStorage<T>(VecStorage<T>);
 
@@ -30,22 +30,172 @@ pub use shared::SharedVecStorage;
 
 pub trait Storage {
     type Item;
+    type Augment: Augment<Self::Item>;
 }
 
+/// Node type of a storage, carrying the summary of its augmentation
+pub(crate) type StorageNode<S> = Node<<S as Storage>::Item, Summary<S>>;
+/// Summary type of the augmentation of a storage
+pub type Summary<S> = <<S as Storage>::Augment as Augment<<S as Storage>::Item>>::Summary;
+
 // Todo: Make create internal
 pub trait InternalStorage: Storage {
-    fn len(&self) -> usize;
-    fn push(&mut self, node: Node<Self::Item>);
+    /// Index the next pushed node will be stored at
+    fn next_index(&self) -> usize;
+    fn push(&mut self, node: StorageNode<Self>);
+    /// Takes the node out of its slot and marks the slot free for reuse. The node must already be unlinked from its tree.
+    fn remove(&mut self, index: usize) -> StorageNode<Self>;
     #[cfg(test)]
-    fn debug_nodes(&self) -> Vec<Node<Self::Item>>
+    fn debug_nodes(&self) -> Vec<StorageNode<Self>>
     where
-        Self::Item: Copy;
+        Self::Item: Copy,
+        Summary<Self>: Clone;
     #[cfg(any(feature = "fuzz", test))]
     fn debug_str(&self) -> String
     where
-        Self::Item: core::fmt::Debug;
-    fn get(&self, index: usize) -> &Node<Self::Item>;
-    fn get_mut(&mut self, index: usize) -> &mut Node<Self::Item>;
+        Self::Item: core::fmt::Debug,
+        Summary<Self>: core::fmt::Debug;
+    /// Generation of the node at `index`, if the slot is occupied. Unlike the other methods, this accepts any index.
+    fn generation(&self, index: usize) -> Option<u32>;
+    /// Distinguishes storages, so handles are only accepted by the storage which created them
+    fn id(&self) -> u32;
+    fn get(&self, index: usize) -> &StorageNode<Self>;
+    fn get_mut(&mut self, index: usize) -> &mut StorageNode<Self>;
+    fn augment(&self) -> &Self::Augment;
+
+    /// Runs `f`, which hands references to items of this storage to user code like comparators.
+    /// Shared storages reject modifications meanwhile, as user code can reach them through other trees.
+    #[inline(always)]
+    fn callback<R>(&self, f: impl FnOnce() -> R) -> R {
+        f()
+    }
+
+    /// Recomputes the summary of a node from its item and the summaries of its children
+    fn update_summary(&mut self, index: usize) {
+        let summary = self.callback(|| {
+            let augment = self.augment();
+            let node = self.get(index);
+            let mut summary = augment.single(&node.value);
+            if let Some(left) = node.left.get() {
+                summary = augment.combine(&self.get(left).summary, &summary);
+            }
+            if let Some(right) = node.right.get() {
+                summary = augment.combine(&summary, &self.get(right).summary);
+            }
+            summary
+        });
+        self.get_mut(index).summary = summary;
+    }
+
+    /// Exchanges the summaries of two nodes without running the augmentation
+    fn swap_summaries(&mut self, a: usize, b: usize);
+
+    /// Updates the summaries from `index` up to the root. Zero-sized summaries carry no information and are skipped.
+    fn update_path(&mut self, mut index: OptionKey) {
+        if core::mem::size_of::<Summary<Self>>() == 0 {
+            return;
+        }
+        while let Some(node_idx) = index.get() {
+            self.update_summary(node_idx);
+            index = self.get(node_idx).parent;
+        }
+    }
+
+    /// Number of items in the subtree of `index`, if the augmentation counts them
+    fn subtree_len(&self, index: OptionKey) -> Option<usize> {
+        let augment = self.augment();
+        self.callback(|| match index.get() {
+            Some(node_idx) => augment.len(&self.get(node_idx).summary),
+            None => augment.len(&augment.empty()),
+        })
+    }
+
+    /// In-order position of the node within its tree, if the augmentation counts items
+    fn position(&self, mut index: usize) -> Option<usize> {
+        let mut position = self.subtree_len(self.get(index).left)?;
+        while let Some(parent) = self.get(index).parent.get() {
+            let parent_node = self.get(parent);
+            if parent_node.right == index {
+                position += self.subtree_len(parent_node.left)? + 1;
+            }
+            index = parent;
+        }
+        Some(position)
+    }
+
+    /// Node at in-order `position` below `index`, if the augmentation counts items
+    fn select(&self, mut index: OptionKey, mut position: usize) -> Option<OptionKey> {
+        while let Some(node_idx) = index.get() {
+            let node = self.get(node_idx);
+            let left_len = self.subtree_len(node.left)?;
+            index = match position.cmp(&left_len) {
+                core::cmp::Ordering::Less => node.left,
+                core::cmp::Ordering::Equal => return Some(index),
+                core::cmp::Ordering::Greater => {
+                    position -= left_len + 1;
+                    node.right
+                }
+            };
+        }
+        Some(OptionKey::none())
+    }
+
+    fn root_of(&self, mut index: usize) -> usize {
+        while let Some(parent) = self.get(index).parent.get() {
+            index = parent;
+        }
+        index
+    }
+
+    fn leftmost(&self, mut index: usize) -> usize {
+        while let Some(left) = self.get(index).left.get() {
+            index = left;
+        }
+        index
+    }
+
+    fn rightmost(&self, mut index: usize) -> usize {
+        while let Some(right) = self.get(index).right.get() {
+            index = right;
+        }
+        index
+    }
+
+    /// Next node in order, found via the right subtree or the first ancestor we're left of
+    fn successor(&self, mut index: usize) -> OptionKey {
+        let node = self.get(index);
+        if let Some(right) = node.right.get() {
+            return OptionKey::new(self.leftmost(right));
+        }
+        let mut parent = node.parent;
+        while let Some((k, parent_node)) = parent.get().map(|k| (k, self.get(k))) {
+            if parent_node.right == index {
+                index = k;
+                parent = parent_node.parent;
+            } else {
+                break;
+            }
+        }
+        parent
+    }
+
+    /// Previous node in order, mirroring [`InternalStorage::successor`]
+    fn predecessor(&self, mut index: usize) -> OptionKey {
+        let node = self.get(index);
+        if let Some(left) = node.left.get() {
+            return OptionKey::new(self.rightmost(left));
+        }
+        let mut parent = node.parent;
+        while let Some((k, parent_node)) = parent.get().map(|k| (k, self.get(k))) {
+            if parent_node.left == index {
+                index = k;
+                parent = parent_node.parent;
+            } else {
+                break;
+            }
+        }
+        parent
+    }
 }
 
 pub trait InternalRefStorage: InternalStorage {}
@@ -1,79 +1,217 @@
 use super::{InternalRefStorage, InternalStorage, Storage};
-use crate::{node::Color, node::Node};
+use crate::{
+    augment::{Augment, NoAugment},
+    key::OptionKey,
+    node::Node,
+};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-pub struct VecStorage<T>(Vec<Node<T>>);
+/// Source of storage ids. Wraps around after u32::MAX storages, like generations do after as many removals.
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+struct Slot<T, S> {
+    /// Incremented whenever the slot is vacated, so handles to former nodes become stale.
+    /// Wraps around after u32::MAX removals from the same slot.
+    generation: u32,
+    state: SlotState<T, S>,
+}
+
+enum SlotState<T, S> {
+    Occupied(Node<T, S>),
+    /// Links to the next vacant slot, forming the free list
+    Vacant(OptionKey),
+}
+
+impl<T, S> Slot<T, S> {
+    #[inline(always)]
+    fn node(&self) -> &Node<T, S> {
+        match &self.state {
+            SlotState::Occupied(node) => node,
+            SlotState::Vacant(_) => vacant_slot(),
+        }
+    }
+
+    #[inline(always)]
+    fn node_mut(&mut self) -> &mut Node<T, S> {
+        match &mut self.state {
+            SlotState::Occupied(node) => node,
+            SlotState::Vacant(_) => vacant_slot(),
+        }
+    }
+}
+
+/// Removed nodes are unlinked from their tree, so the library never reaches them again
+#[cold]
+fn vacant_slot() -> ! {
+    unreachable!("Accessed a removed node")
+}
+
+pub struct VecStorage<T, A: Augment<T> = NoAugment> {
+    slots: Vec<Slot<T, A::Summary>>,
+    /// Head of the free list, which is threaded through vacant slots
+    free: OptionKey,
+    augment: A,
+    id: u32,
+}
 
 impl<T> VecStorage<T> {
     pub(crate) fn new() -> Self {
-        Self(Vec::new())
+        Self::with_augment(NoAugment)
     }
+}
 
-    pub(crate) fn new_with(value: T) -> Self {
-        let mut node: Node<_> = value.into();
-        node.color = Color::Black;
-        Self(alloc::vec![node])
+impl<T, A: Augment<T>> VecStorage<T, A> {
+    /// Empty storage whose nodes keep summaries of `augment`
+    pub fn with_augment(augment: A) -> Self {
+        Self {
+            slots: Vec::new(),
+            free: OptionKey::none(),
+            augment,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
     }
 }
 
-impl<T> Storage for VecStorage<T> {
+impl<T, A: Augment<T>> VecStorage<T, A> {
+    /// Moves the subtree at `index` into `dest`, keeping its shape, colors and summaries. Returns the index of its root in `dest`.
+    pub(crate) fn move_subtree(
+        &mut self,
+        index: usize,
+        dest: &mut Self,
+        parent: OptionKey,
+    ) -> usize {
+        let mut node = self.remove(index);
+        let (left, right) = (node.left, node.right);
+        node.parent = parent;
+        let dest_idx = dest.next_index();
+        dest.push(node);
+
+        // Recursion is bounded by the height of the tree
+        let mut move_child = |child: OptionKey| match child.get() {
+            Some(child_idx) => {
+                OptionKey::new(self.move_subtree(child_idx, dest, OptionKey::new(dest_idx)))
+            }
+            None => OptionKey::none(),
+        };
+        let (left, right) = (move_child(left), move_child(right));
+        let node = dest.get_mut(dest_idx);
+        node.left = left;
+        node.right = right;
+        dest_idx
+    }
+}
+
+impl<T, A: Augment<T>> Storage for VecStorage<T, A> {
     type Item = T;
+    type Augment = A;
 }
 
-impl<T> InternalStorage for VecStorage<T> {
-    // Returns number of nodes from all trees and deleted nodes.
-    fn len(&self) -> usize {
-        self.0.len()
+impl<T, A: Augment<T>> InternalStorage for VecStorage<T, A> {
+    // Vacated slots are reused before the vec grows
+    fn next_index(&self) -> usize {
+        self.free.get().unwrap_or(self.slots.len())
+    }
+
+    fn push(&mut self, node: Node<T, A::Summary>) {
+        match self.free.get() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                let SlotState::Vacant(next_free) = slot.state else {
+                    unreachable!("Free list points to occupied slot {index}")
+                };
+                self.free = next_free;
+                slot.state = SlotState::Occupied(node);
+            }
+            None => self.slots.push(Slot {
+                generation: 0,
+                state: SlotState::Occupied(node),
+            }),
+        }
     }
 
-    fn push(&mut self, node: Node<T>) {
-        self.0.push(node)
+    fn remove(&mut self, index: usize) -> Node<T, A::Summary> {
+        let slot = &mut self.slots[index];
+        match core::mem::replace(&mut slot.state, SlotState::Vacant(self.free)) {
+            SlotState::Occupied(node) => {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free = OptionKey::new(index);
+                node
+            }
+            SlotState::Vacant(_) => unreachable!("Removed node {index} twice"),
+        }
     }
 
     #[cfg(test)]
-    fn debug_nodes(&self) -> Vec<Node<T>>
+    fn debug_nodes(&self) -> Vec<Node<T, A::Summary>>
     where
         T: Copy,
+        A::Summary: Clone,
     {
-        self.0.clone()
+        self.slots
+            .iter()
+            .filter_map(|x| match &x.state {
+                SlotState::Occupied(node) => Some(node.clone()),
+                SlotState::Vacant(_) => None,
+            })
+            .collect()
     }
     #[cfg(any(feature = "fuzz", test))]
     fn debug_str(&self) -> String
     where
         Self::Item: std::fmt::Debug,
+        A::Summary: std::fmt::Debug,
     {
-        self.0
+        self.slots
             .iter()
-            .map(|x| format!("{x:?}"))
+            .map(|x| match &x.state {
+                SlotState::Occupied(node) => format!("{node:?}"),
+                SlotState::Vacant(next_free) => {
+                    format!("Vacant {{ next_free: {next_free:?} }}")
+                }
+            })
             .collect::<Vec<_>>()
             .join("\n")
     }
 
+    fn generation(&self, index: usize) -> Option<u32> {
+        self.slots
+            .get(index)
+            .filter(|slot| matches!(slot.state, SlotState::Occupied(_)))
+            .map(|slot| slot.generation)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Indices are checked even though the library only creates valid ones. A panicking augmentation can't
+    // corrupt the tree, but checks keep a broken invariant from reading out of bounds.
     #[inline(always)]
-    fn get(&self, index: usize) -> &Node<T> {
-        #[cfg(debug_assertions)]
-        {
-            &self.0[index]
-        }
-        /// Safety: Is using only indices created by this library
-        #[cfg(not(debug_assertions))]
-        unsafe {
-            self.0.get_unchecked(index)
-        }
+    fn get(&self, index: usize) -> &Node<T, A::Summary> {
+        self.slots[index].node()
     }
+
     #[inline(always)]
-    fn get_mut(&mut self, index: usize) -> &mut Node<T> {
-        #[cfg(debug_assertions)]
-        {
-            &mut self.0[index]
-        }
+    fn get_mut(&mut self, index: usize) -> &mut Node<T, A::Summary> {
+        self.slots[index].node_mut()
+    }
 
-        /// Safety: Is using only indices created by this library
-        #[cfg(not(debug_assertions))]
-        unsafe {
-            self.0.get_unchecked_mut(index)
+    fn swap_summaries(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
         }
+        let (low, high) = (a.min(b), a.max(b));
+        let (head, tail) = self.slots.split_at_mut(high);
+        core::mem::swap(
+            &mut head[low].node_mut().summary,
+            &mut tail[0].node_mut().summary,
+        );
+    }
+
+    fn augment(&self) -> &A {
+        &self.augment
     }
 }
 
-impl<T> InternalRefStorage for VecStorage<T> {}
+impl<T, A: Augment<T>> InternalRefStorage for VecStorage<T, A> {}
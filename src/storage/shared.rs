@@ -1,10 +1,26 @@
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 
 use super::{owned::VecStorage, InternalStorage, Storage};
-use crate::{node::Node, Color, RedBlackTreeSet};
+use crate::{
+    augment::{Augment, NoAugment},
+    key::OptionKey,
+    node::Node,
+    Color, Compare, HandleError, NodeHandle, OrdCompare, OrderStatistic, RedBlackTreeSet, COUNTED,
+};
 
-pub struct SharedVecStorage<T> {
-    nodes: UnsafeCell<VecStorage<T>>,
+pub struct SharedVecStorage<T, A: Augment<T> = NoAugment> {
+    nodes: UnsafeCell<VecStorage<T, A>>,
+    /// Number of running user callbacks, which might hold references to items
+    callbacks: Cell<usize>,
+}
+
+/// Counts a running callback until it returns or unwinds
+struct CallbackGuard<'a>(&'a Cell<usize>);
+
+impl Drop for CallbackGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
 }
 
 impl<T> Default for SharedVecStorage<T> {
@@ -15,39 +31,279 @@ impl<T> Default for SharedVecStorage<T> {
 
 impl<T> SharedVecStorage<T> {
     pub fn new() -> Self {
+        Self::with_augment(NoAugment)
+    }
+}
+
+impl<T, A: Augment<T>> SharedVecStorage<T, A> {
+    /// Empty storage whose nodes keep summaries of `augment`
+    pub fn with_augment(augment: A) -> Self {
         Self {
-            nodes: VecStorage::new().into(),
+            nodes: VecStorage::with_augment(augment).into(),
+            callbacks: Cell::new(0),
         }
     }
 
-    pub fn add_tree(&self, value: T) -> RedBlackTreeSet<&SharedVecStorage<T>> {
+    /// Modifications might move items, so they are rejected while callbacks could still reference them
+    fn check_no_callback(&self) {
+        assert!(
+            self.callbacks.get() == 0,
+            "SharedVecStorage can't be modified from comparators or augmentations of its trees"
+        );
+    }
+
+    pub fn add_tree(&self, value: T) -> RedBlackTreeSet<&SharedVecStorage<T, A>> {
+        let summary = self.callback(|| self.augment().single(&value));
+        self.check_no_callback();
         let this = unsafe { &mut *self.nodes.get() };
-        let root = this.len();
-        let mut node: Node<_> = value.into();
+        let root = OptionKey::new(this.next_index());
+        let mut node = Node::new(value, summary);
         node.color = Color::Black;
         this.push(node);
-        RedBlackTreeSet { nodes: self, root }
+        RedBlackTreeSet {
+            nodes: self,
+            root,
+            len: 1,
+            compare: OrdCompare,
+        }
+    }
+
+    pub fn add_empty_tree(&self) -> RedBlackTreeSet<&SharedVecStorage<T, A>> {
+        self.add_empty_tree_with_comparator(OrdCompare)
+    }
+
+    /// Adds an empty tree ordered by `compare` instead of [`Ord`]
+    pub fn add_empty_tree_with_comparator<C>(
+        &self,
+        compare: C,
+    ) -> RedBlackTreeSet<&SharedVecStorage<T, A>, C> {
+        RedBlackTreeSet {
+            nodes: self,
+            root: OptionKey::none(),
+            len: 0,
+            compare,
+        }
+    }
+}
+
+/// Reasons why [`RedBlackTreeSet::move_to`] refused to move a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// The destination already contains an equal item
+    Occupied(NodeHandle),
+    /// The handle doesn't refer to a node of the source tree
+    Handle(HandleError),
+}
+
+impl From<HandleError> for MoveError {
+    fn from(value: HandleError) -> Self {
+        MoveError::Handle(value)
+    }
+}
+
+impl core::fmt::Display for MoveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MoveError::Occupied(_) => f.write_str("destination already contains an equal item"),
+            MoveError::Handle(e) => e.fmt(f),
+        }
     }
 }
 
-impl<'a, T> Storage for &'a SharedVecStorage<T> {
+#[cfg(feature = "std")]
+impl std::error::Error for MoveError {}
+
+impl<'a, T, A: Augment<T>, C: Compare<T>> RedBlackTreeSet<&'a SharedVecStorage<T, A>, C> {
+    /// Moves the node of `handle` into `dest` without relocating its value. The handle stays valid and refers to the node in `dest` afterwards.
+    ///
+    /// Panics if `dest` doesn't share the storage with `self`
+    pub fn move_to(
+        &mut self,
+        handle: NodeHandle,
+        dest: &mut RedBlackTreeSet<&'a SharedVecStorage<T, A>, C>,
+    ) -> Result<(), MoveError> {
+        let node_idx = self.check_handle(handle)?;
+        self.move_node(node_idx, dest)
+    }
+
+    /// Moves all nodes of `other` into `self` without relocating values. Handles of `other` refer to the nodes in `self` afterwards.
+    /// Items equal to an item of `self` are dropped, as in [`RedBlackTreeSet::insert`].
+    ///
+    /// If all items of one tree are ordered before the other, both are joined in O(log n + log m).
+    /// Otherwise the nodes of the smaller tree are relinked one by one, which takes O(min(n, m) log(n + m)).
+    ///
+    /// The structure of both trees is reused, so their comparators have to order alike. Zero-sized comparators like
+    /// [`OrdCompare`] have no state to differ in. Others might be configured differently at runtime, so all items of
+    /// `other` are checked to be ordered by the comparator of `self` first, which takes O(m).
+    /// If they aren't, `other` is returned unchanged.
+    ///
+    /// Panics if `other` doesn't share the storage with `self`
+    pub fn append(
+        &mut self,
+        mut other: RedBlackTreeSet<&'a SharedVecStorage<T, A>, C>,
+    ) -> Result<(), RedBlackTreeSet<&'a SharedVecStorage<T, A>, C>> {
+        assert!(
+            core::ptr::eq(self.nodes, other.nodes),
+            "Trees can only be appended within the same storage"
+        );
+        if core::mem::size_of::<C>() != 0 && !other.is_ordered_by(&self.compare) {
+            return Err(other);
+        }
+        let (Some(first), Some(last), Some(other_first), Some(other_last)) = (
+            self.first_node(),
+            self.last_node(),
+            other.first_node(),
+            other.last_node(),
+        ) else {
+            if self.is_empty() {
+                self.root = other.root;
+                self.len = other.len;
+            }
+            return Ok(());
+        };
+
+        let compare_nodes = |tree: &Self, left: usize, right: usize| {
+            let nodes = &tree.nodes;
+            nodes.callback(|| {
+                tree.compare
+                    .compare(&nodes.get(left).value, &nodes.get(right).value)
+            })
+        };
+        // The pivot is taken from `other`, so its rest joins with `self` on the matching side
+        if compare_nodes(self, last, other_first).is_lt() {
+            other.unlink(other_first);
+            let left = (self.root, self.black_height(self.root));
+            let right = (other.root, self.black_height(other.root));
+            self.len += other.len;
+            self.join(left, other_first, right);
+        } else if compare_nodes(self, other_last, first).is_lt() {
+            other.unlink(other_last);
+            let left = (other.root, self.black_height(other.root));
+            let right = (self.root, self.black_height(self.root));
+            self.len += other.len;
+            self.join(left, other_last, right);
+        } else {
+            // The larger structure is kept by swapping roots, as both trees share the storage
+            let self_moves = other.len > self.len;
+            if self_moves {
+                core::mem::swap(&mut self.root, &mut other.root);
+                core::mem::swap(&mut self.len, &mut other.len);
+            }
+            while let Some(node_idx) = other.first_node() {
+                other.unlink(node_idx);
+                match self.locate(&self.nodes.get(node_idx).value) {
+                    // Items of the original `self` are kept on either side
+                    Ok(existing_idx) if self_moves => {
+                        self.substitute(existing_idx, node_idx);
+                        self.nodes.remove(existing_idx);
+                    }
+                    Ok(_) => {
+                        self.nodes.remove(node_idx);
+                    }
+                    Err((parent_idx, is_right)) => {
+                        self.len += 1;
+                        self.link(node_idx, parent_idx, is_right);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits off the items which aren't below `key` into a new tree of the same storage in O(log n), like [`alloc::collections::BTreeSet::split_off`].
+    /// No value is relocated and all handles stay valid.
+    ///
+    /// The split-off items are counted by the [`OrderStatistic`] augmentation, which keeps the split logarithmic.
+    pub fn split_off<Q: ?Sized>(
+        &mut self,
+        key: &Q,
+    ) -> RedBlackTreeSet<&'a SharedVecStorage<T, A>, C>
+    where
+        A: OrderStatistic<T>,
+        C: Compare<T, Q> + Clone,
+    {
+        let root = self.split_off_root(key);
+        let len = self.nodes.subtree_len(root).expect(COUNTED);
+        self.len -= len;
+        RedBlackTreeSet {
+            nodes: self.nodes,
+            root,
+            len,
+            compare: self.compare.clone(),
+        }
+    }
+
+    /// Whether each item is ordered before the next one by `compare`
+    fn is_ordered_by(&self, compare: &C) -> bool {
+        let mut current = self.first_node();
+        while let Some(node_idx) = current {
+            let next = self.nodes.successor(node_idx).get();
+            if let Some(next_idx) = next {
+                let (value, next_value) = (
+                    &self.nodes.get(node_idx).value,
+                    &self.nodes.get(next_idx).value,
+                );
+                if !self
+                    .nodes
+                    .callback(|| compare.compare(value, next_value))
+                    .is_lt()
+                {
+                    return false;
+                }
+            }
+            current = next;
+        }
+        true
+    }
+
+    pub(crate) fn move_node(
+        &mut self,
+        node_idx: usize,
+        dest: &mut RedBlackTreeSet<&'a SharedVecStorage<T, A>, C>,
+    ) -> Result<(), MoveError> {
+        assert!(
+            core::ptr::eq(self.nodes, dest.nodes),
+            "Nodes can only be moved between trees of the same storage"
+        );
+        let (parent_idx, is_right) = match dest.locate(&self.nodes.get(node_idx).value) {
+            Ok(existing_idx) => return Err(MoveError::Occupied(dest.handle(existing_idx))),
+            Err(position) => position,
+        };
+        self.len -= 1;
+        self.unlink(node_idx);
+        dest.len += 1;
+        dest.link(node_idx, parent_idx, is_right);
+        Ok(())
+    }
+}
+
+impl<T, A: Augment<T>> Storage for &SharedVecStorage<T, A> {
     type Item = T;
+    type Augment = A;
 }
 
 /// Safety: Unsafe is ok, because the type is !Sync. Send could be implemented, because all Trees have to be destroyed before this type can be moved
-/// Each tree accesses it's own elements. Therefore, no runtime-guard is necessary. Trees with SharedVecStorage must never return references (otherwise the library would be unsound)
-impl<'a, T> InternalStorage for &'a SharedVecStorage<T> {
-    fn len(&self) -> usize {
-        unsafe { &*self.nodes.get() }.len()
+/// Each tree accesses it's own elements. Trees with SharedVecStorage must never return references (otherwise the library would be unsound).
+/// User callbacks get references to items, but can reach sibling trees. These are guarded to reject modifications while the callback runs.
+impl<T, A: Augment<T>> InternalStorage for &SharedVecStorage<T, A> {
+    fn next_index(&self) -> usize {
+        unsafe { &*self.nodes.get() }.next_index()
     }
 
-    fn push(&mut self, node: Node<Self::Item>) {
+    fn push(&mut self, node: Node<T, A::Summary>) {
+        self.check_no_callback();
         unsafe { &mut *self.nodes.get() }.push(node)
     }
+
+    fn remove(&mut self, index: usize) -> Node<T, A::Summary> {
+        self.check_no_callback();
+        unsafe { &mut *self.nodes.get() }.remove(index)
+    }
     #[cfg(test)]
-    fn debug_nodes(&self) -> Vec<Node<Self::Item>>
+    fn debug_nodes(&self) -> Vec<Node<T, A::Summary>>
     where
         Self::Item: Copy,
+        A::Summary: Clone,
     {
         unsafe { &*self.nodes.get() }.debug_nodes()
     }
@@ -56,15 +312,40 @@ impl<'a, T> InternalStorage for &'a SharedVecStorage<T> {
     fn debug_str(&self) -> String
     where
         Self::Item: core::fmt::Debug,
+        A::Summary: core::fmt::Debug,
     {
         unsafe { &*self.nodes.get() }.debug_str()
     }
 
-    fn get(&self, index: usize) -> &Node<Self::Item> {
+    fn generation(&self, index: usize) -> Option<u32> {
+        unsafe { &*self.nodes.get() }.generation(index)
+    }
+
+    fn id(&self) -> u32 {
+        unsafe { &*self.nodes.get() }.id()
+    }
+
+    fn get(&self, index: usize) -> &Node<T, A::Summary> {
         unsafe { &*self.nodes.get() }.get(index)
     }
 
-    fn get_mut(&mut self, index: usize) -> &mut Node<Self::Item> {
+    fn get_mut(&mut self, index: usize) -> &mut Node<T, A::Summary> {
+        self.check_no_callback();
         unsafe { &mut *self.nodes.get() }.get_mut(index)
     }
+
+    fn swap_summaries(&mut self, a: usize, b: usize) {
+        self.check_no_callback();
+        unsafe { &mut *self.nodes.get() }.swap_summaries(a, b)
+    }
+
+    fn augment(&self) -> &A {
+        unsafe { &*self.nodes.get() }.augment()
+    }
+
+    fn callback<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.callbacks.set(self.callbacks.get() + 1);
+        let _guard = CallbackGuard(&self.callbacks);
+        f()
+    }
 }